@@ -0,0 +1,109 @@
+//! Benchmarks of the hot path through `cleanse_field` and `run`.
+//!
+//! Run with `cargo bench`. These use a small std timing harness, reporting the mean time per
+//! iteration over a fixed time budget, so they need no extra dependencies.
+use cleanse::encoding::InputEncoding;
+use cleanse::{cleanse_field, get_input, run, CleanseOptions};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How long to spend measuring each benchmark, after warming up.
+const BUDGET: Duration = Duration::from_secs(2);
+
+/// Time `f`, printing the mean time per iteration.
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    // Warm up, and estimate how many iterations fit in the budget
+    let start = Instant::now();
+    let mut warmup = 0u64;
+    while start.elapsed() < BUDGET / 10 {
+        f();
+        warmup += 1;
+    }
+    let iterations = (warmup * 10).max(1);
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let per_iter = start.elapsed() / iterations as u32;
+    println!(
+        "{:<32} {:>12?}/iter ({} iterations)",
+        name, per_iter, iterations
+    );
+}
+
+fn bench_cleanse_field(opts: &CleanseOptions) {
+    let fields: [(&str, &[u8]); 4] = [
+        ("cleanse_field/clean", b"a perfectly ordinary field value"),
+        (
+            "cleanse_field/delimiter",
+            b"a field\twith\tdelimiters\tin it",
+        ),
+        ("cleanse_field/newline", b"a field\nwith\r\nnewlines\nin it"),
+        (
+            "cleanse_field/invalid_utf8",
+            b"a field \xff\xfe with \xc3 bad bytes",
+        ),
+    ];
+    for (name, field) in fields {
+        bench(name, || {
+            black_box(cleanse_field(black_box(field), opts, 0, 0));
+        });
+    }
+}
+
+fn bench_run(opts: &CleanseOptions) {
+    let input = records();
+    let mut output = Vec::with_capacity(input.len());
+    bench("run/100_000_records", || {
+        output.clear();
+        black_box(run(black_box(input.as_slice()), &mut output, opts).unwrap());
+    });
+}
+
+/// Reading the same records from a file, through the default and a 1MB read buffer, and through
+/// a memory map.
+fn bench_file_input(opts: &CleanseOptions) {
+    let path = std::env::temp_dir().join(format!("cleanse_bench_{}.tsv", std::process::id()));
+    std::fs::write(&path, records()).unwrap();
+    let mut output = vec![];
+    for (name, mmap, buffer_size) in [
+        ("run/file", false, None),
+        ("run/file_1mb_buffer", false, Some(1024 * 1024)),
+        ("run/mmap", true, None),
+    ] {
+        bench(name, || {
+            output.clear();
+            let input = get_input(
+                Some(path.clone()),
+                InputEncoding::Utf8,
+                None,
+                mmap,
+                buffer_size,
+            )
+            .unwrap();
+            black_box(run(input, &mut output, opts).unwrap());
+        });
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// 100 000 records, a few of which need cleansing.
+fn records() -> Vec<u8> {
+    let mut input = vec![];
+    for i in 0..100_000 {
+        match i % 10 {
+            0 => input.extend_from_slice(b"\"has\ta delimiter\"\t2\t3\t4\n"),
+            1 => input.extend_from_slice(b"\"has a\nnewline\"\t2\t3\t4\n"),
+            _ => input.extend_from_slice(b"clean field\t2\t3\t4\n"),
+        }
+    }
+    input
+}
+
+fn main() {
+    let opts = CleanseOptions::default();
+    bench_cleanse_field(&opts);
+    bench_run(&opts);
+    bench_file_input(&opts);
+}
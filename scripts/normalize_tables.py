@@ -0,0 +1,53 @@
+"""Generate src/normalize_tables.rs from Python's copy of the Unicode Character Database.
+
+Run from the root of the repository: python3 scripts/normalize_tables.py
+"""
+import unicodedata as u
+
+decomp=[];ccc=[];comp=[]
+for cp in range(0x110000):
+    if 0xD800<=cp<=0xDFFF: continue
+    c=chr(cp)
+    d=u.decomposition(c)
+    if d and not d.startswith('<'):
+        parts=[int(x,16) for x in d.split()]
+        assert len(parts) in (1,2)
+        decomp.append((cp,parts[0],parts[1] if len(parts)==2 else 0))
+        if len(parts)==2 and u.normalize('NFC',c)==c:
+            comp.append((parts[0],parts[1],cp))
+    k=u.combining(c)
+    if k:
+        if ccc and ccc[-1][1]==cp-1 and ccc[-1][2]==k: ccc[-1][1]=cp
+        else: ccc.append([cp,cp,k])
+comp.sort()
+# `normalize::nfc` leaves anything before U+0300 as is without looking it up
+for cp in range(0x300):
+    assert u.combining(chr(cp))==0
+assert not any(a<0x300 and b<0x300 for a,b,_ in comp)
+def ch(x): return "'\\u{%x}'"%x if x else "'\\0'"
+def emit(name,ty,rows,fmt):
+    out=["pub(crate) const %s: &[%s] = &["%(name,ty)]
+    line="   "
+    for r in rows:
+        item=" "+fmt(r)+","
+        if len(line)+len(item)>100:
+            out.append(line); line="   "
+        line+=item
+    out.append(line); out.append("];")
+    return "\n".join(out)
+hdr='''//! Tables for [`crate::normalize`], generated by `scripts/normalize_tables.py` from version
+//! %s of the Unicode Character Database shipped with Python's `unicodedata` module.
+'''%u.unidata_version
+body=[hdr]
+body.append("/// Each character with a canonical decomposition, and the one or two characters it decomposes to,\n/// `'\\0'` if it decomposes to one. Sorted by character.")
+body.append("#[rustfmt::skip]")
+body.append(emit("DECOMPOSITIONS","(char, char, char)",decomp,lambda r:"(%s, %s, %s)"%tuple(ch(x) for x in r)))
+body.append("")
+body.append("/// Each range of characters with the same non-zero canonical combining class, and that class.\n/// Sorted by range.")
+body.append("#[rustfmt::skip]")
+body.append(emit("COMBINING_CLASSES","(char, char, u8)",ccc,lambda r:"(%s, %s, %d)"%(ch(r[0]),ch(r[1]),r[2])))
+body.append("")
+body.append("/// Each pair of characters with a primary composite, and that composite, sorted by pair.")
+body.append("#[rustfmt::skip]")
+body.append(emit("COMPOSITIONS","(char, char, char)",comp,lambda r:"(%s, %s, %s)"%tuple(ch(x) for x in r)))
+open('src/normalize_tables.rs','w').write("\n".join(body)+"\n")
@@ -0,0 +1,63 @@
+//! Standard base64 encoding, with padding, for writing binary fields as text.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `bytes` as base64 using the standard alphabet, padded with `=`.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Decode padded standard base64, for checking what was encoded.
+    fn decode(encoded: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        for chunk in encoded.as_bytes().chunks(4) {
+            let values: Vec<u32> = chunk
+                .iter()
+                .take_while(|&&c| c != b'=')
+                .map(|c| ALPHABET.iter().position(|a| a == c).unwrap() as u32)
+                .collect();
+            let group = values
+                .iter()
+                .enumerate()
+                .fold(0, |group, (i, value)| group | value << (18 - 6 * i));
+            for i in 0..values.len() - 1 {
+                bytes.push((group >> (16 - 8 * i)) as u8);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_encode() {
+        for (input, expected) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+            (b"\xff\xfe\x00", "//4A"),
+        ] {
+            assert_eq!(encode(input), expected);
+            assert_eq!(decode(expected), input);
+        }
+    }
+}
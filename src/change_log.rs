@@ -0,0 +1,62 @@
+//! A machine readable log of every change made to every field.
+use crate::error::CleanseError;
+use crate::FieldChange;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Writes a TSV with one row per changed field.
+///
+/// The columns are `record_number`, `field_number`, `change_type`, `original_bytes_hex`, and
+/// `new_value`. The `change_type` is the snake case [`crate::CleanseChanges::name`] of the change,
+/// and when a field had more than one kind of change they are comma separated.
+pub struct ChangeLog {
+    writer: csv::Writer<Box<dyn Write>>,
+}
+
+impl ChangeLog {
+    /// Create a change log writing to `output`, writing the header row immediately.
+    pub fn new(output: Box<dyn Write>) -> Result<Self, CleanseError> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_writer(output);
+        writer.write_record([
+            "record_number",
+            "field_number",
+            "change_type",
+            "original_bytes_hex",
+            "new_value",
+        ])?;
+        Ok(Self { writer })
+    }
+
+    /// Log the changes made to a single field, `changes` must all be for the same field.
+    pub fn log(&mut self, changes: &[FieldChange]) -> Result<(), CleanseError> {
+        let change = match changes.first() {
+            Some(change) => change,
+            None => return Ok(()),
+        };
+        let change_type = changes
+            .iter()
+            .map(|change| change.kind.name())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut original_hex = String::with_capacity(change.original.len() * 2);
+        for byte in &change.original {
+            write!(original_hex, "{:02x}", byte).expect("Writing to a String can't fail");
+        }
+        self.writer.write_record([
+            change.record.to_string().as_str(),
+            change.field.to_string().as_str(),
+            change_type.as_str(),
+            original_hex.as_str(),
+            change.cleaned.as_str(),
+        ])?;
+        Ok(())
+    }
+
+    /// Flush the change log to its output.
+    pub fn flush(&mut self) -> Result<(), CleanseError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
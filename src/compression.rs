@@ -0,0 +1,91 @@
+//! The compression formats input may be read in and output written in.
+use crate::error::CleanseError;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A compression format, detected from a file extension when not given explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    /// Recognised so that it's reported as unsupported rather than read as plain bytes.
+    Zstd,
+    /// Recognised so that it's reported as unsupported rather than read as plain bytes.
+    Bzip2,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            "bzip2" | "bz2" => Ok(Compression::Bzip2),
+            _ => Err(format!(
+                "Unknown compression {:?}, expected one of gzip, zstd, bzip2, none",
+                s
+            )),
+        }
+    }
+}
+
+impl Compression {
+    /// The compression `path` looks to be in based on its extension, `None` for paths without a
+    /// recognised extension.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Compression {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some(extension) if extension.eq_ignore_ascii_case("gz") => Compression::Gzip,
+            Some(extension) if extension.eq_ignore_ascii_case("zst") => Compression::Zstd,
+            Some(extension) if extension.eq_ignore_ascii_case("bz2") => Compression::Bzip2,
+            _ => Compression::None,
+        }
+    }
+
+    /// Check that this compression can actually be read and written, only gzip and none can.
+    pub fn check_supported(self) -> Result<Self, CleanseError> {
+        match self {
+            Compression::None | Compression::Gzip => Ok(self),
+            Compression::Zstd | Compression::Bzip2 => Err(CleanseError::InvalidOptions(format!(
+                "{:?} compression is not supported",
+                self
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(Compression::from_extension("a.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_extension("a.csv.GZ"), Compression::Gzip);
+        assert_eq!(Compression::from_extension("a.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_extension("a.bz2"), Compression::Bzip2);
+        assert_eq!(Compression::from_extension("a.csv"), Compression::None);
+        assert_eq!(Compression::from_extension("-"), Compression::None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("GZ".parse::<Compression>().unwrap(), Compression::Gzip);
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert_eq!("zst".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("xz".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_check_supported() {
+        assert!(Compression::Gzip.check_supported().is_ok());
+        assert!(Compression::None.check_supported().is_ok());
+        assert!(Compression::Zstd.check_supported().is_err());
+        assert!(Compression::Bzip2.check_supported().is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Config files, written in the subset of TOML needed to set command line options.
+//!
+//! Each `key = value` pair sets the option of the same name, with `_` and `-` treated alike.
+//! Values may be strings, integers, booleans, or single line arrays of those. Tables such as
+//! `[output]` may be used to group keys, but don't change their meaning.
+use crate::error::CleanseError;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A single value in a config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<ConfigValue>),
+}
+
+/// The keys and values of a config file, in the order they were given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub entries: Vec<(String, ConfigValue)>,
+}
+
+impl Config {
+    /// Read and parse the config file at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, CleanseError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        contents.parse().map_err(|e| {
+            CleanseError::InvalidOptions(format!("Invalid config file {:?}: {}", path, e))
+        })
+    }
+
+    /// Get the value of `key`, if it was set.
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
+}
+
+impl FromStr for Config {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Config::default();
+        for (i, line) in s.lines().enumerate() {
+            parse_line(line, &mut config).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Keys are stored in the kebab case used by command line options.
+fn normalize_key(key: &str) -> String {
+    key.replace('_', "-")
+}
+
+fn parse_line(line: &str, config: &mut Config) -> Result<(), String> {
+    let mut cursor = Cursor::new(line);
+    cursor.skip_whitespace();
+    match cursor.peek() {
+        None | Some('#') => return Ok(()),
+        Some('[') => {
+            if cursor.rest().starts_with("[[") {
+                return Err("Arrays of tables are not supported".to_string());
+            }
+            let end = cursor
+                .rest()
+                .find(']')
+                .ok_or_else(|| "Unclosed table header".to_string())?;
+            cursor.advance(end + 1);
+            return cursor.finish();
+        }
+        _ => {}
+    }
+    let key = cursor.parse_key()?;
+    cursor.skip_whitespace();
+    if cursor.next() != Some('=') {
+        return Err(format!("Expected = after key {:?}", key));
+    }
+    cursor.skip_whitespace();
+    let value = cursor.parse_value()?;
+    cursor.finish()?;
+    let key = normalize_key(&key);
+    if config.get(&key).is_some() {
+        return Err(format!("Duplicate key {:?}", key));
+    }
+    config.entries.push((key, value));
+    Ok(())
+}
+
+struct Cursor<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(s: &'a str) -> Self {
+        Self { s, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.pos += n;
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Check that nothing but whitespace or a comment is left on the line.
+    fn finish(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None | Some('#') => Ok(()),
+            Some(_) => Err(format!("Unexpected trailing {:?}", self.rest())),
+        }
+    }
+
+    fn parse_key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => self.parse_basic_string(),
+            Some('\'') => self.parse_literal_string(),
+            _ => {
+                let len = self
+                    .rest()
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(self.rest().len());
+                if len == 0 {
+                    return Err(format!("Expected a key, found {:?}", self.rest()));
+                }
+                let key = self.rest()[..len].to_string();
+                self.advance(len);
+                if self.peek() == Some('.') {
+                    return Err("Dotted keys are not supported".to_string());
+                }
+                Ok(key)
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<ConfigValue, String> {
+        match self.peek() {
+            Some('"') => Ok(ConfigValue::String(self.parse_basic_string()?)),
+            Some('\'') => Ok(ConfigValue::String(self.parse_literal_string()?)),
+            Some('[') => self.parse_array(),
+            Some(_) => {
+                let len = self
+                    .rest()
+                    .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+                    .unwrap_or(self.rest().len());
+                let token = &self.rest()[..len];
+                let value = match token {
+                    "true" => ConfigValue::Boolean(true),
+                    "false" => ConfigValue::Boolean(false),
+                    _ => ConfigValue::Integer(
+                        token
+                            .replace('_', "")
+                            .parse()
+                            .map_err(|_| format!("Unsupported value {:?}", token))?,
+                    ),
+                };
+                self.advance(len);
+                Ok(value)
+            }
+            None => Err("Expected a value".to_string()),
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<ConfigValue, String> {
+        self.next();
+        let mut values = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(ConfigValue::Array(values));
+            }
+            let value = self.parse_value()?;
+            if let ConfigValue::Array(_) = value {
+                return Err("Nested arrays are not supported".to_string());
+            }
+            values.push(value);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(ConfigValue::Array(values)),
+                _ => return Err("Arrays must be closed on the same line".to_string()),
+            }
+        }
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, String> {
+        self.next();
+        let end = self
+            .rest()
+            .find('\'')
+            .ok_or_else(|| "Unclosed string".to_string())?;
+        let s = self.rest()[..end].to_string();
+        self.advance(end + 1);
+        Ok(s)
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, String> {
+        self.next();
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('t') => '\t',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(u @ 'u') | Some(u @ 'U') => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let hex = self
+                                .rest()
+                                .get(..len)
+                                .ok_or_else(|| "Truncated unicode escape".to_string())?;
+                            let c = u32::from_str_radix(hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("Invalid unicode escape {:?}", hex))?;
+                            self.advance(len);
+                            c
+                        }
+                        other => return Err(format!("Unsupported escape {:?}", other)),
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+                None => return Err("Unclosed string".to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config: Config = r#"
+            # Shared settings for the nightly export
+            delimiter = "\t"
+            output_delimiter = ','  # a literal string
+            batch-size = 2_048
+            has_headers = true
+
+            [cleaning]
+            regex_replace = ["\\s+= ", 'N/A=']
+            replacement = "·"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.entries,
+            vec![
+                (
+                    "delimiter".to_string(),
+                    ConfigValue::String("\t".to_string())
+                ),
+                (
+                    "output-delimiter".to_string(),
+                    ConfigValue::String(",".to_string())
+                ),
+                ("batch-size".to_string(), ConfigValue::Integer(2048)),
+                ("has-headers".to_string(), ConfigValue::Boolean(true)),
+                (
+                    "regex-replace".to_string(),
+                    ConfigValue::Array(vec![
+                        ConfigValue::String("\\s+= ".to_string()),
+                        ConfigValue::String("N/A=".to_string())
+                    ])
+                ),
+                (
+                    "replacement".to_string(),
+                    ConfigValue::String("·".to_string())
+                ),
+            ]
+        );
+        assert_eq!(config.get("batch_size"), Some(&ConfigValue::Integer(2048)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (input, err) in [
+            ("delimiter", "line 1: Expected = after key \"delimiter\""),
+            ("a = 1\nb = \"x", "line 2: Unclosed string"),
+            ("a = 1\na = 2", "line 2: Duplicate key \"a\""),
+            ("a = 1 2", "line 1: Unexpected trailing \"2\""),
+            ("a = [1, [2]]", "line 1: Nested arrays are not supported"),
+            ("a = [1,", "line 1: Expected a value"),
+            ("a.b = 1", "line 1: Dotted keys are not supported"),
+            ("a = 1.5", "line 1: Unsupported value \"1.5\""),
+        ] {
+            assert_eq!(input.parse::<Config>().unwrap_err(), err);
+        }
+    }
+}
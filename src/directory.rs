@@ -0,0 +1,85 @@
+//! Discovery of the input files to cleanse in a directory.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Find the regular files directly inside `directory` whose names match the glob `pattern`,
+/// sorted by name.
+pub fn find_files<P: AsRef<Path>>(directory: P, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            if glob_match(pattern, name) {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Check if `name` matches the glob `pattern`, where `*` matches any run of characters and `?`
+/// matches any single character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume from if the most recent `*` needs to match more characters
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.csv", "data.csv"));
+        assert!(glob_match("*.csv", ".csv"));
+        assert!(!glob_match("*.csv", "data.tsv"));
+        assert!(!glob_match("*.csv", "data.csv.gz"));
+        assert!(glob_match("*.csv*", "data.csv.gz"));
+        assert!(glob_match("data_??.csv", "data_01.csv"));
+        assert!(!glob_match("data_??.csv", "data_1.csv"));
+        assert!(glob_match("*_*_*.csv", "a_b_c_d.csv"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn test_find_files() {
+        let dir = std::env::temp_dir().join(format!("cleanse_find_files_{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested.csv")).unwrap();
+        for name in ["b.csv", "a.csv", "c.tsv"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let files = find_files(&dir, "*.csv").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, vec![dir.join("a.csv"), dir.join("b.csv")]);
+    }
+}
@@ -0,0 +1,385 @@
+//! Transcoding of non UTF-8 input into UTF-8 before it is parsed.
+use std::io::{self, BufRead, Read};
+use std::str::FromStr;
+
+/// The encodings input may be read as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEncoding {
+    Utf8,
+    /// Latin-1 as decoded by [`Latin1Reader`], which is really windows-1252: bytes 0x80 to 0x9F
+    /// are read as the characters windows-1252 puts there, not as ISO-8859-1 control characters.
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+    /// Guess the encoding from the start of the input with [`detect_encoding`].
+    Detect,
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(InputEncoding::Utf8),
+            // Not iso-8859-1, as bytes 0x80 to 0x9F aren't read as its control characters
+            "latin1" | "latin-1" | "windows-1252" | "cp1252" => Ok(InputEncoding::Latin1),
+            "utf16le" | "utf-16le" => Ok(InputEncoding::Utf16Le),
+            "utf16be" | "utf-16be" => Ok(InputEncoding::Utf16Be),
+            "detect" => Ok(InputEncoding::Detect),
+            _ => Err(format!(
+                "Unknown encoding {:?}, expected one of utf8, latin1, utf16le, utf16be, detect",
+                s
+            )),
+        }
+    }
+}
+
+/// The byte order mark some tools write at the start of UTF-8 files.
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Discard a UTF-8 BOM from the start of `reader` if there is one, returning whether there was.
+pub fn strip_bom<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// The byte order marks UTF-16 files start with.
+pub const UTF16_LE_BOM: &[u8] = b"\xFF\xFE";
+pub const UTF16_BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Discard a UTF-16 BOM from the start of `reader` if there is one, returning the encoding it
+/// marks.
+pub fn strip_utf16_bom<R: BufRead>(reader: &mut R) -> io::Result<Option<InputEncoding>> {
+    let buf = reader.fill_buf()?;
+    let encoding = if buf.starts_with(UTF16_LE_BOM) {
+        InputEncoding::Utf16Le
+    } else if buf.starts_with(UTF16_BE_BOM) {
+        InputEncoding::Utf16Be
+    } else {
+        return Ok(None);
+    };
+    reader.consume(2);
+    Ok(Some(encoding))
+}
+
+/// The number of bytes from the start of the input that [`detect_encoding`] is given.
+pub const DETECT_SAMPLE_LEN: usize = 4096;
+
+/// Guess which encoding `sample`, the start of some input, is in.
+///
+/// This is a crude heuristic standing in for a real detector such as chardetng, which isn't a
+/// dependency, and only tells UTF-8, UTF-16, and Latin-1 apart. Input that is valid UTF-8,
+/// allowing for a character cut off at the end of the sample, is UTF-8. Otherwise input where at
+/// least a quarter of the code units have a zero high byte, as ASCII text does, and at most a
+/// quarter as many have a zero low byte, is UTF-16 of that byte order. Anything else, including
+/// Latin-1 with runs of NUL bytes such as padding, is taken to be Latin-1.
+pub fn detect_encoding(sample: &[u8]) -> InputEncoding {
+    match std::str::from_utf8(sample) {
+        Ok(_) => return InputEncoding::Utf8,
+        Err(err) if err.error_len().is_none() => return InputEncoding::Utf8,
+        Err(_) => {}
+    }
+    let units = sample.len() / 2;
+    let zeros_at = |offset: usize| {
+        sample
+            .chunks_exact(2)
+            .filter(|unit| unit[offset] == 0)
+            .count()
+    };
+    // A run of NUL bytes puts as many zeros at either offset, UTF-16 text puts them at one
+    let (low, high) = (zeros_at(0), zeros_at(1));
+    if units > 0 && high * 4 >= units && low * 4 <= high {
+        InputEncoding::Utf16Le
+    } else if units > 0 && low * 4 >= units && high * 4 <= low {
+        InputEncoding::Utf16Be
+    } else {
+        InputEncoding::Latin1
+    }
+}
+
+/// A reader that transcodes UTF-16 to UTF-8.
+///
+/// Unpaired surrogates, and a trailing odd byte, are replaced with the Unicode replacement
+/// character.
+#[derive(Debug)]
+pub struct Utf16Reader<R> {
+    inner: R,
+    big_endian: bool,
+    buffer: Vec<u8>,
+    pos: usize,
+    /// Bytes read that don't yet make up a whole code unit, or a high surrogate and its pair.
+    carry: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Utf16Reader<R> {
+    pub fn new(inner: R, big_endian: bool) -> Self {
+        Self {
+            inner,
+            big_endian,
+            buffer: Vec::with_capacity(8 * 1024),
+            pos: 0,
+            carry: Vec::with_capacity(4),
+            eof: false,
+        }
+    }
+
+    fn unit(&self, bytes: &[u8]) -> u16 {
+        if self.big_endian {
+            u16::from_be_bytes([bytes[0], bytes[1]])
+        } else {
+            u16::from_le_bytes([bytes[0], bytes[1]])
+        }
+    }
+
+    /// Transcode the next chunk of input into the buffer.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+        while self.buffer.is_empty() && !self.eof {
+            let mut raw = [0; 4 * 1024];
+            let n = self.inner.read(&mut raw)?;
+            if n == 0 {
+                self.eof = true;
+                if !self.carry.is_empty() {
+                    // Whatever is left can't be a whole character
+                    self.buffer.extend_from_slice("\u{FFFD}".as_bytes());
+                    self.carry.clear();
+                }
+                break;
+            }
+            let mut bytes = std::mem::take(&mut self.carry);
+            bytes.extend_from_slice(&raw[..n]);
+            let mut whole = bytes.len() / 2 * 2;
+            // Hold back a trailing high surrogate until its pair has been read
+            if whole >= 2 && (0xD800..0xDC00).contains(&self.unit(&bytes[whole - 2..whole])) {
+                whole -= 2;
+            }
+            let units: Vec<u16> = bytes[..whole].chunks(2).map(|b| self.unit(b)).collect();
+            for c in char::decode_utf16(units) {
+                let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+                let mut utf8 = [0; 4];
+                self.buffer
+                    .extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+            self.carry.extend_from_slice(&bytes[whole..]);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Utf16Reader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            self.fill()?;
+        }
+        let n = out.len().min(self.buffer.len() - self.pos);
+        out[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The characters windows-1252 has in place of the C1 control characters 0x80 to 0x9F of
+/// ISO-8859-1, with the five bytes it leaves undefined kept as their control characters.
+const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// A reader that transcodes Latin-1 bytes to UTF-8.
+///
+/// Like most software labelling text Latin-1, this decodes it as windows-1252: bytes 0x80 to 0x9F
+/// are its punctuation and letters, such as `€` and curly quotes, rather than the C1 control
+/// characters of strict ISO-8859-1. Every other byte maps to the Unicode code point of the same
+/// value, so bytes below 0x80 pass through.
+#[derive(Debug)]
+pub struct Latin1Reader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> Latin1Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(8 * 1024),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Latin1Reader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            let mut raw = [0; 4 * 1024];
+            let n = self.inner.read(&mut raw)?;
+            self.buffer.clear();
+            self.pos = 0;
+            for &byte in &raw[..n] {
+                if byte < 0x80 {
+                    self.buffer.push(byte);
+                } else if byte < 0xA0 {
+                    let c = WINDOWS_1252_C1[usize::from(byte - 0x80)];
+                    self.buffer
+                        .extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                } else {
+                    self.buffer.push(0xC0 | (byte >> 6));
+                    self.buffer.push(0x80 | (byte & 0x3F));
+                }
+            }
+        }
+        let n = out.len().min(self.buffer.len() - self.pos);
+        out[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_bom() {
+        let mut reader = &b"\xEF\xBB\xBFa,b\n"[..];
+        assert!(strip_bom(&mut reader).unwrap());
+        assert_eq!(reader, b"a,b\n");
+
+        let mut reader = &b"a,b\n"[..];
+        assert!(!strip_bom(&mut reader).unwrap());
+        assert_eq!(reader, b"a,b\n");
+    }
+
+    fn utf16(s: &str, big_endian: bool) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|unit| {
+                if big_endian {
+                    unit.to_be_bytes()
+                } else {
+                    unit.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_utf16() {
+        let text = "naïve,😀\nb,c\n";
+        for big_endian in [false, true] {
+            let mut out = vec![];
+            Utf16Reader::new(&utf16(text, big_endian)[..], big_endian)
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), text);
+        }
+
+        // One byte at a time splits both code units and surrogate pairs
+        struct OneByte<'a>(&'a [u8]);
+        impl Read for OneByte<'_> {
+            fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(1).min(out.len());
+                out[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let bytes = utf16(text, false);
+        let mut out = vec![];
+        Utf16Reader::new(OneByte(&bytes), false)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), text);
+
+        // A lone surrogate and a trailing odd byte are replaced
+        let mut out = vec![];
+        Utf16Reader::new(&b"a\x00\x00\xD8b\x00c"[..], false)
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\u{FFFD}b\u{FFFD}");
+    }
+
+    #[test]
+    fn test_strip_utf16_bom() {
+        let mut reader = &b"\xFF\xFEa\x00"[..];
+        assert_eq!(
+            strip_utf16_bom(&mut reader).unwrap(),
+            Some(InputEncoding::Utf16Le)
+        );
+        assert_eq!(reader, b"a\x00");
+
+        let mut reader = &b"\xFE\xFF\x00a"[..];
+        assert_eq!(
+            strip_utf16_bom(&mut reader).unwrap(),
+            Some(InputEncoding::Utf16Be)
+        );
+
+        let mut reader = &b"a,b\n"[..];
+        assert_eq!(strip_utf16_bom(&mut reader).unwrap(), None);
+        assert_eq!(reader, b"a,b\n");
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        assert_eq!(detect_encoding(b""), InputEncoding::Utf8);
+        assert_eq!(detect_encoding("a,café\n".as_bytes()), InputEncoding::Utf8);
+        // Cut off in the middle of the "é"
+        assert_eq!(detect_encoding(b"a,caf\xC3"), InputEncoding::Utf8);
+        assert_eq!(detect_encoding(b"a,caf\xe9\n"), InputEncoding::Latin1);
+        // NUL padding isn't taken for the zero bytes of UTF-16
+        assert_eq!(
+            detect_encoding(b"id,name\x00\x00\x00\x00\x00\x00\x00\x00,caf\xe9\n"),
+            InputEncoding::Latin1
+        );
+        assert_eq!(
+            detect_encoding(b"a,caf\xe9\x00\x00\x00\x00\x00\x00\x00\x00\x00\n"),
+            InputEncoding::Latin1
+        );
+
+        let utf16 = |big_endian: bool| -> Vec<u8> {
+            "a,Zoë\n"
+                .encode_utf16()
+                .flat_map(|unit| {
+                    if big_endian {
+                        unit.to_be_bytes()
+                    } else {
+                        unit.to_le_bytes()
+                    }
+                })
+                .collect()
+        };
+        assert_eq!(detect_encoding(&utf16(false)), InputEncoding::Utf16Le);
+        assert_eq!(detect_encoding(&utf16(true)), InputEncoding::Utf16Be);
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(
+            "Windows-1252".parse::<InputEncoding>().unwrap(),
+            InputEncoding::Latin1
+        );
+        // Bytes 0x80 to 0x9F aren't read as ISO-8859-1 would
+        assert!("iso-8859-1".parse::<InputEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_latin1() {
+        let mut reader = Latin1Reader::new(&b"caf\xe9,na\xefve"[..]);
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"caf\xc3\xa9,na\xc3\xafve");
+        assert_eq!(String::from_utf8(out).unwrap(), "café,naïve");
+
+        // 0x80 to 0x9F are read as windows-1252
+        let mut reader = Latin1Reader::new(&b"\x80 \x93q\x94 \x81"[..]);
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "\u{20AC} \u{201C}q\u{201D} \u{0081}");
+    }
+}
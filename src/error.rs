@@ -0,0 +1,170 @@
+//! The error type returned by the library.
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while cleansing.
+#[derive(Debug)]
+pub enum CleanseError {
+    /// Reading the input or writing an output failed.
+    Io(io::Error),
+    /// The input couldn't be parsed, or a record couldn't be written.
+    Csv(csv::Error),
+    /// A delimiter or quote character isn't usable, with a description of why.
+    InvalidDelimiter(String),
+    /// Some other option or config value isn't usable, with a description of why.
+    InvalidOptions(String),
+    /// A record didn't have the expected number of fields.
+    FieldCountMismatch {
+        record: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A field wasn't valid UTF-8, and fixing the encoding was turned off.
+    EncodingError { record: usize, field: usize },
+    /// A record had fewer fields than the minimum, or more than the maximum, given by `limit`.
+    FieldCountOutOfRange {
+        record: usize,
+        limit: usize,
+        found: usize,
+    },
+    /// A field in a required column was empty.
+    RequiredFieldEmpty { record: usize, field: usize },
+    /// More than `max_ratio` of the fields in the first `records` records would be changed.
+    ChangeRatioExceeded {
+        records: usize,
+        ratio: f64,
+        max_ratio: f64,
+    },
+    /// A header name was given to more than one column, at the 0-based `columns`.
+    DuplicateHeader { name: String, columns: Vec<usize> },
+    /// The output was closed before everything was written to it, such as by `| head`.
+    BrokenPipe,
+}
+
+impl CleanseError {
+    /// Check if this is a broken pipe, which callers writing to stdout usually want to ignore.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, CleanseError::BrokenPipe)
+    }
+}
+
+impl fmt::Display for CleanseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CleanseError::Io(err) => write!(f, "{}", err),
+            CleanseError::Csv(err) => write!(f, "{}", err),
+            CleanseError::InvalidDelimiter(msg) | CleanseError::InvalidOptions(msg) => {
+                f.write_str(msg)
+            }
+            CleanseError::FieldCountMismatch {
+                record,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Record number {}: expected {} fields, found {}",
+                record, expected, found
+            ),
+            CleanseError::EncodingError { record, field } => write!(
+                f,
+                "Record number {}, field number {}: invalid UTF-8",
+                record, field
+            ),
+            CleanseError::FieldCountOutOfRange {
+                record,
+                limit,
+                found,
+            } => write!(
+                f,
+                "Record number {}: expected {} {} fields, found {}",
+                record,
+                if found < limit { "at least" } else { "at most" },
+                limit,
+                found
+            ),
+            CleanseError::RequiredFieldEmpty { record, field } => write!(
+                f,
+                "Record number {}, field number {}: required field is empty",
+                record, field
+            ),
+            CleanseError::ChangeRatioExceeded {
+                records,
+                ratio,
+                max_ratio,
+            } => write!(
+                f,
+                "{:.1}% of the fields in the first {} records would be changed, more than the \
+                 maximum of {:.1}%",
+                ratio * 100.0,
+                records,
+                max_ratio * 100.0
+            ),
+            CleanseError::DuplicateHeader { name, columns } => write!(
+                f,
+                "Duplicate header {:?} in columns {}",
+                name,
+                columns
+                    .iter()
+                    .map(|column| column.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            CleanseError::BrokenPipe => f.write_str("Broken pipe"),
+        }
+    }
+}
+
+impl Error for CleanseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CleanseError::Io(err) => Some(err),
+            CleanseError::Csv(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CleanseError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::BrokenPipe {
+            CleanseError::BrokenPipe
+        } else {
+            CleanseError::Io(err)
+        }
+    }
+}
+
+impl From<csv::Error> for CleanseError {
+    fn from(err: csv::Error) -> Self {
+        match err.kind() {
+            csv::ErrorKind::Io(io_err) if io_err.kind() == io::ErrorKind::BrokenPipe => {
+                CleanseError::BrokenPipe
+            }
+            _ => CleanseError::Csv(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for CleanseError {
+    fn from(err: serde_json::Error) -> Self {
+        // Only writing can fail, so this is always an underlying I/O error
+        CleanseError::from(io::Error::from(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_broken_pipe() {
+        let err = CleanseError::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(err.is_broken_pipe());
+        let err = CleanseError::from(csv::Error::from(io::Error::from(io::ErrorKind::BrokenPipe)));
+        assert!(err.is_broken_pipe());
+        let err = CleanseError::from(io::Error::from(io::ErrorKind::NotFound));
+        assert!(!err.is_broken_pipe());
+        assert!(err.source().is_some());
+    }
+}
@@ -0,0 +1,73 @@
+//! A separate CSV output of just the records that were changed, for reviewing them by hand.
+use crate::error::CleanseError;
+use crate::output::csv_writer_builder;
+use crate::CleanseOptions;
+use csv::ByteRecord;
+use std::io::Write;
+
+/// Writes every record with at least one change or violation, as it was written to the main
+/// output, or would have been if it wasn't dropped.
+///
+/// The records are always delimited text with the same delimiter, quoting, and terminator as
+/// [`crate::output::OutputFormat::Csv`] output of the first run written to it would use, whatever
+/// the output format. The header is written once, and later runs are written the same way even
+/// if they detect a different delimiter, so that the error output stays a single valid file.
+pub struct ErrorOutput {
+    output: Option<Box<dyn Write>>,
+    writer: Option<csv::Writer<Box<dyn Write>>>,
+    wrote_header: bool,
+}
+
+impl ErrorOutput {
+    /// Create an error output writing to `output`.
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self {
+            output: Some(output),
+            writer: None,
+            wrote_header: false,
+        }
+    }
+
+    /// The writer for the records, made for the options of the first run that writes to it so
+    /// that it uses that run's detected delimiter, and kept for every later run.
+    fn writer(&mut self, opts: &CleanseOptions) -> &mut csv::Writer<Box<dyn Write>> {
+        let output = &mut self.output;
+        self.writer.get_or_insert_with(|| {
+            csv_writer_builder(opts)
+                .from_writer(output.take().expect("The writer is only made once"))
+        })
+    }
+
+    /// Write the header, unless one has already been written.
+    pub fn write_header(
+        &mut self,
+        header: &ByteRecord,
+        opts: &CleanseOptions,
+    ) -> Result<(), CleanseError> {
+        if !self.wrote_header {
+            self.writer(opts).write_byte_record(header)?;
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    /// Write a single changed record.
+    pub fn write_record(
+        &mut self,
+        record: &ByteRecord,
+        opts: &CleanseOptions,
+    ) -> Result<(), CleanseError> {
+        self.writer(opts).write_byte_record(record)?;
+        Ok(())
+    }
+
+    /// Flush the error output to its output.
+    pub fn flush(&mut self) -> Result<(), CleanseError> {
+        match (&mut self.writer, &mut self.output) {
+            (Some(writer), _) => writer.flush()?,
+            (None, Some(output)) => output.flush()?,
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Reading records from fixed width input, where each field takes up a set number of bytes of
+//! every line rather than being delimited.
+use csv::ByteRecord;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// The width in bytes of each field of a line, parsed from a comma separated list such as
+/// `10,20,15`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedWidths {
+    pub widths: Vec<usize>,
+}
+
+impl FromStr for FixedWidths {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let widths = s
+            .split(',')
+            .map(|width| match width.trim().parse::<usize>() {
+                Ok(0) => Err(String::from("Field widths must be at least 1")),
+                Ok(width) => Ok(width),
+                Err(_) => Err(format!("Invalid field width {:?}", width)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { widths })
+    }
+}
+
+/// Splits each line of the input into fields of [`FixedWidths`], trimming the spaces used to pad
+/// each field. Lines shorter than the total width are treated as if padded with spaces, and any
+/// bytes past the total width are ignored.
+pub struct FixedWidthReader<R> {
+    input: R,
+    widths: Vec<usize>,
+    terminator: u8,
+    line: Vec<u8>,
+    bytes_read: u64,
+}
+
+impl<R: BufRead> FixedWidthReader<R> {
+    /// Read lines ending in `terminator` from `input`.
+    pub fn new(input: R, widths: &FixedWidths, terminator: u8) -> Self {
+        Self {
+            input,
+            widths: widths.widths.clone(),
+            terminator,
+            line: vec![],
+            bytes_read: 0,
+        }
+    }
+
+    /// Read the next non-empty line into `record`, returning `false` at the end of the input.
+    pub fn read_byte_record(&mut self, record: &mut ByteRecord) -> io::Result<bool> {
+        loop {
+            self.line.clear();
+            let len = self.input.read_until(self.terminator, &mut self.line)?;
+            if len == 0 {
+                return Ok(false);
+            }
+            self.bytes_read += len as u64;
+            let mut line = self.line.as_slice();
+            if let Some(rest) = line.strip_suffix(&[self.terminator]) {
+                line = rest;
+            }
+            if self.terminator == b'\n' {
+                line = line.strip_suffix(b"\r").unwrap_or(line);
+            }
+            // Blank lines are skipped, like the CSV reader does
+            if line.is_empty() {
+                continue;
+            }
+
+            record.clear();
+            let mut start = 0;
+            for &width in &self.widths {
+                let end = (start + width).min(line.len());
+                let field = &line[start.min(end)..end];
+                let padding = field.iter().rev().take_while(|&&b| b == b' ').count();
+                record.push_field(&field[..field.len() - padding]);
+                start += width;
+            }
+            return Ok(true);
+        }
+    }
+
+    /// The number of bytes read from the input so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_widths() {
+        assert_eq!(
+            "10, 20,15".parse::<FixedWidths>().unwrap().widths,
+            vec![10, 20, 15]
+        );
+        assert!("10,0".parse::<FixedWidths>().is_err());
+        assert!("10,x".parse::<FixedWidths>().is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let widths: FixedWidths = "3,5,2".parse().unwrap();
+        let input = &b"abcde  fgh\r\n\nxy z\n12345678901234"[..];
+        let mut reader = FixedWidthReader::new(input, &widths, b'\n');
+        let mut record = ByteRecord::new();
+
+        assert!(reader.read_byte_record(&mut record).unwrap());
+        assert_eq!(record, vec!["abc", "de  f", "gh"]);
+        // Short lines are padded, so their missing fields are empty
+        assert!(reader.read_byte_record(&mut record).unwrap());
+        assert_eq!(record, vec!["xy", "z", ""]);
+        assert!(reader.read_byte_record(&mut record).unwrap());
+        assert_eq!(record, vec!["123", "45678", "90"]);
+        assert!(!reader.read_byte_record(&mut record).unwrap());
+        assert_eq!(reader.bytes_read(), input.len() as u64);
+    }
+}
@@ -0,0 +1,354 @@
+//! Streaming gzip (RFC 1952) compression and decompression on top of raw DEFLATE.
+use miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide};
+use miniz_oxide::deflate::stream::deflate;
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+use std::io::{self, BufRead, Read, Write};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const METHOD_DEFLATE: u8 = 8;
+const FLAG_HCRC: u8 = 1 << 1;
+const FLAG_EXTRA: u8 = 1 << 2;
+const FLAG_NAME: u8 = 1 << 3;
+const FLAG_COMMENT: u8 = 1 << 4;
+/// The default compression level, matching gzip's own default.
+pub const DEFAULT_LEVEL: u8 = 6;
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// A running CRC-32 as used in the gzip trailer.
+#[derive(Debug, Default, Clone, Copy)]
+struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    fn update(&mut self, bytes: &[u8]) {
+        let mut crc = !self.crc;
+        for &byte in bytes {
+            crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+        }
+        self.crc = !crc;
+    }
+
+    fn sum(&self) -> u32 {
+        self.crc
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecoderState {
+    /// Expecting the header of the first member.
+    FirstHeader,
+    /// Expecting either the header of another member or the end of input.
+    NextHeader,
+    Body,
+    Trailer,
+    Done,
+}
+
+/// Decompresses a gzip stream, including streams with multiple members.
+pub struct GzDecoder<R> {
+    inner: R,
+    state: DecoderState,
+    inflate: Box<InflateState>,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            state: DecoderState::FirstHeader,
+            inflate: InflateState::new_boxed(DataFormat::Raw),
+            crc: Crc32::default(),
+            size: 0,
+        }
+    }
+
+    /// Read and validate a member header, returning false if the input has ended instead.
+    fn read_header(&mut self, first: bool) -> io::Result<bool> {
+        if !first && self.inner.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+        let mut header = [0; 10];
+        self.inner.read_exact(&mut header)?;
+        if header[..2] != MAGIC {
+            return Err(invalid_data("Input is not gzip compressed"));
+        }
+        if header[2] != METHOD_DEFLATE {
+            return Err(invalid_data("Unsupported gzip compression method"));
+        }
+        let flags = header[3];
+        if flags & FLAG_EXTRA != 0 {
+            let mut len = [0; 2];
+            self.inner.read_exact(&mut len)?;
+            let mut extra = vec![0; u16::from_le_bytes(len) as usize];
+            self.inner.read_exact(&mut extra)?;
+        }
+        if flags & FLAG_NAME != 0 {
+            self.inner.read_until(0, &mut vec![])?;
+        }
+        if flags & FLAG_COMMENT != 0 {
+            self.inner.read_until(0, &mut vec![])?;
+        }
+        if flags & FLAG_HCRC != 0 {
+            self.inner.read_exact(&mut [0; 2])?;
+        }
+        Ok(true)
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0; 8];
+        self.inner.read_exact(&mut trailer)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != self.crc.sum() || size != self.size {
+            return Err(invalid_data("Corrupt gzip stream, checksum mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.state {
+                DecoderState::FirstHeader | DecoderState::NextHeader => {
+                    let first = self.state == DecoderState::FirstHeader;
+                    if self.read_header(first)? {
+                        self.inflate.reset(DataFormat::Raw);
+                        self.crc = Crc32::default();
+                        self.size = 0;
+                        self.state = DecoderState::Body;
+                    } else {
+                        self.state = DecoderState::Done;
+                    }
+                }
+                DecoderState::Body => {
+                    let input = self.inner.fill_buf()?;
+                    let eof = input.is_empty();
+                    let flush = if eof { MZFlush::Finish } else { MZFlush::None };
+                    let result = inflate(&mut self.inflate, input, out, flush);
+                    self.inner.consume(result.bytes_consumed);
+                    let written = &out[..result.bytes_written];
+                    self.crc.update(written);
+                    self.size = self.size.wrapping_add(written.len() as u32);
+                    match result.status {
+                        Ok(MZStatus::StreamEnd) => self.state = DecoderState::Trailer,
+                        Ok(_) if eof && result.bytes_written == 0 => {
+                            return Err(io::ErrorKind::UnexpectedEof.into())
+                        }
+                        Ok(_) => {}
+                        Err(_) => return Err(invalid_data("Corrupt gzip stream")),
+                    }
+                    if result.bytes_written > 0 {
+                        return Ok(result.bytes_written);
+                    }
+                }
+                DecoderState::Trailer => {
+                    self.read_trailer()?;
+                    self.state = DecoderState::NextHeader;
+                }
+                DecoderState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Compresses everything written to it as a single gzip member.
+///
+/// The stream is finished when the encoder is dropped, or explicitly with [`GzEncoder::finish`]
+/// to observe any errors.
+pub struct GzEncoder<W: Write> {
+    inner: Option<W>,
+    compressor: Box<CompressorOxide>,
+    buffer: Vec<u8>,
+    crc: Crc32,
+    size: u32,
+    wrote_header: bool,
+}
+
+impl<W: Write> GzEncoder<W> {
+    /// Create an encoder with a compression level from 0 (none) to 9 (best).
+    pub fn new(inner: W, level: u8) -> Self {
+        let flags = create_comp_flags_from_zip_params(level.min(9) as i32, -15, 0);
+        Self {
+            inner: Some(inner),
+            compressor: Box::new(CompressorOxide::new(flags)),
+            buffer: vec![0; 32 * 1024],
+            crc: Crc32::default(),
+            size: 0,
+            wrote_header: false,
+        }
+    }
+
+    fn inner(&mut self) -> &mut W {
+        self.inner.as_mut().expect("GzEncoder used after finish")
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            // No flags, no modification time, no extra flags, unknown OS
+            let header = [MAGIC[0], MAGIC[1], METHOD_DEFLATE, 0, 0, 0, 0, 0, 0, 0xFF];
+            self.inner().write_all(&header)?;
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    /// Compress `input` with `flush`, writing all output produced to the inner writer.
+    fn compress(&mut self, mut input: &[u8], flush: MZFlush) -> io::Result<()> {
+        self.write_header()?;
+        loop {
+            let result = deflate(&mut self.compressor, input, &mut self.buffer, flush);
+            input = &input[result.bytes_consumed..];
+            let written = result.bytes_written;
+            let inner = self.inner.as_mut().expect("GzEncoder used after finish");
+            inner.write_all(&self.buffer[..written])?;
+            match result.status {
+                Ok(MZStatus::StreamEnd) => return Ok(()),
+                Ok(_) if input.is_empty() && written < self.buffer.len() => return Ok(()),
+                Ok(_) => {}
+                Err(_) if input.is_empty() && written == 0 => return Ok(()),
+                Err(_) => return Err(io::Error::other("Failed to gzip compress output")),
+            }
+        }
+    }
+
+    /// Finish the gzip stream, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().expect("GzEncoder used after finish"))
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if self.inner.is_none() {
+            return Ok(());
+        }
+        self.compress(&[], MZFlush::Finish)?;
+        let crc = self.crc.sum().to_le_bytes();
+        let size = self.size.to_le_bytes();
+        let inner = self.inner();
+        inner.write_all(&crc)?;
+        inner.write_all(&size)?;
+        inner.flush()
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.compress(buf, MZFlush::None)?;
+        self.crc.update(buf);
+        self.size = self.size.wrapping_add(buf.len() as u32);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.compress(&[], MZFlush::Sync)?;
+        self.inner().flush()
+    }
+}
+
+impl<W: Write> Drop for GzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            // Errors can't be surfaced from drop, use `finish` to observe them
+            let _ = self.try_finish();
+            self.inner = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], DEFAULT_LEVEL);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        GzDecoder::new(data).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| format!("{},\"{}\"\n", i, i * 7).into_bytes())
+            .collect();
+        let compressed = compress(&data);
+        assert_eq!(&compressed[..2], &MAGIC);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress(&compressed), data);
+        assert_eq!(decompress(&compress(b"")), b"");
+    }
+
+    #[test]
+    fn test_multiple_members() {
+        let mut compressed = compress(b"a,b\n");
+        compressed.extend(compress(b"c,d\n"));
+        assert_eq!(decompress(&compressed), b"a,b\nc,d\n");
+    }
+
+    #[test]
+    fn test_flush_mid_stream() {
+        let mut encoder = GzEncoder::new(vec![], DEFAULT_LEVEL);
+        encoder.write_all(b"a,b\n").unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(b"c,d\n").unwrap();
+        assert_eq!(decompress(&encoder.finish().unwrap()), b"a,b\nc,d\n");
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut compressed = compress(b"a,b\n");
+        let len = compressed.len();
+        compressed[len - 5] ^= 0xFF;
+        assert!(GzDecoder::new(&compressed[..])
+            .read_to_end(&mut vec![])
+            .is_err());
+        assert!(GzDecoder::new(&b"a,b\n"[..])
+            .read_to_end(&mut vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::default();
+        crc.update(b"123456789");
+        assert_eq!(crc.sum(), 0xCBF4_3926);
+    }
+}
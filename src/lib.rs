@@ -0,0 +1,4625 @@
+//! Clean up delimited (TSV/CSV) data to make it consumable by standard unix tools.
+//!
+//! The main entry point is [`run`], which reads delimited records from any [`Read`], cleanses
+//! each field with [`cleanse_field`], and writes the result to any [`Write`].
+pub mod base64;
+pub mod change_log;
+pub mod compression;
+pub mod config;
+pub mod directory;
+pub mod encoding;
+pub mod error;
+pub mod error_output;
+pub mod fixed_width;
+pub mod gzip;
+pub mod mmap;
+pub mod normalize;
+mod normalize_tables;
+pub mod output;
+pub mod progress;
+mod serialize;
+pub mod watch;
+
+use crate::change_log::ChangeLog;
+use crate::compression::Compression;
+use crate::encoding::{
+    detect_encoding, strip_bom, strip_utf16_bom, InputEncoding, Latin1Reader, Utf16Reader,
+    DETECT_SAMPLE_LEN,
+};
+pub use crate::error::CleanseError;
+use crate::error_output::ErrorOutput;
+use crate::fixed_width::{FixedWidthReader, FixedWidths};
+use crate::gzip::{GzDecoder, GzEncoder};
+use crate::mmap::Mmap;
+use crate::normalize::UnicodeNormalization;
+use crate::output::{LineEnding, OutputFormat, RecordWriter, SharedOutput};
+use crate::progress::Progress;
+use crate::watch::Follow;
+use bstr::{ByteSlice, ByteVec};
+use csv::{ByteRecord, QuoteStyle};
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// The kinds of changes that may be made to a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanseChanges {
+    NullByteReplacement,
+    DelimiterReplacement,
+    TerminatorReplacement,
+    CarriageReturnReplacement,
+    FixedEncoding,
+    RegexReplacement,
+    WhitespaceTrimmed,
+    FieldTruncated,
+    ControlCharReplacement,
+    EmptyFieldReplaced,
+    NumericNormalized,
+    WhitespaceCollapsed,
+    Base64Encoded,
+    HexEncoded,
+    ValueMapped,
+    UnicodeNormalized,
+}
+
+impl fmt::Display for CleanseChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            CleanseChanges::NullByteReplacement => "null byte replaced",
+            CleanseChanges::DelimiterReplacement => "delimiter replaced",
+            CleanseChanges::TerminatorReplacement => "terminator replaced",
+            CleanseChanges::CarriageReturnReplacement => "carriage return replaced",
+            CleanseChanges::FixedEncoding => "non-UTF-8 bytes fixed",
+            CleanseChanges::RegexReplacement => "regex replaced",
+            CleanseChanges::WhitespaceTrimmed => "whitespace trimmed",
+            CleanseChanges::FieldTruncated => "field truncated",
+            CleanseChanges::ControlCharReplacement => "control character replaced",
+            CleanseChanges::EmptyFieldReplaced => "empty field replaced",
+            CleanseChanges::NumericNormalized => "number normalized",
+            CleanseChanges::WhitespaceCollapsed => "whitespace collapsed",
+            CleanseChanges::Base64Encoded => "non-UTF-8 field base64 encoded",
+            CleanseChanges::HexEncoded => "non-UTF-8 bytes hex escaped",
+            CleanseChanges::ValueMapped => "value mapped",
+            CleanseChanges::UnicodeNormalized => "unicode normalized",
+        };
+        f.write_str(description)
+    }
+}
+
+/// The kinds of problems that may be found with a field as it will be written. Fields with them
+/// are left as is, so they aren't counted as changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    PatternNotMatched,
+    RequiredFieldEmpty,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Violation::PatternNotMatched => "pattern not matched",
+            Violation::RequiredFieldEmpty => "required field empty",
+        })
+    }
+}
+
+/// Options controlling how [`run`] reads, cleanses, and writes records.
+#[derive(Debug, Clone)]
+pub struct CleanseOptions {
+    /// Delimiter to use for parsing the input.
+    pub delimiter: u8,
+    /// Delimiter to use for writing the output.
+    pub output_delimiter: u8,
+    /// Text to replace any delimiters, terminators, or other bytes removed from fields with.
+    pub replacement: String,
+    /// Text to replace delimiters found inside of fields with instead of
+    /// [`CleanseOptions::replacement`].
+    pub delimiter_replacement: Option<String>,
+    /// Text to replace `\n`, `\r\n`, and `\r` found inside of fields with instead of
+    /// [`CleanseOptions::replacement`].
+    pub terminator_replacement: Option<String>,
+    /// Scan and log all changes, but write nothing to the output.
+    pub dry_run: bool,
+    /// Treat the first record as a header and write it through without cleansing it.
+    pub has_headers: bool,
+    /// Read the header but leave it out of [`OutputFormat::Csv`] output, for appending to output
+    /// that already has the header. Other formats still use it for their keys.
+    pub skip_header: bool,
+    /// Header names to replace, any not found in the header are warned about.
+    pub renames: Vec<ColumnRename>,
+    /// Convert every header name to this case, after any renames.
+    pub header_case: Option<HeaderCase>,
+    /// Number of records to read into memory and cleanse in parallel at a time.
+    pub batch_size: usize,
+    /// Number of threads to cleanse each batch of records with.
+    pub threads: usize,
+    /// Number to give the first record read, so numbering can continue across several inputs.
+    pub first_record_number: usize,
+    /// Show a progress indicator on stderr.
+    pub progress: bool,
+    /// Size of the input in bytes if known, used to show the percentage complete.
+    pub input_size: Option<u64>,
+    /// Regex substitutions to apply to each field after all other fixes, in order.
+    pub regex_replacements: Vec<RegexReplace>,
+    /// When to quote fields in the output.
+    pub quote_style: QuoteStyle,
+    /// The format to write records in.
+    pub output_format: OutputFormat,
+    /// Strip leading and trailing ASCII whitespace from each field.
+    pub trim: bool,
+    /// Drop any record with more than this many changes across all of its fields.
+    pub max_errors_per_record: Option<usize>,
+    /// Abort before writing anything if more than this fraction of the fields in the first
+    /// [`CHANGE_RATIO_SAMPLE`] records would be changed, as the input is likely in a different
+    /// format than expected.
+    pub max_change_ratio: Option<f64>,
+    /// Quote character to use for parsing and writing.
+    pub quote: u8,
+    /// The only columns to cleanse, all other fields are written verbatim.
+    pub columns: Option<Columns>,
+    /// Columns to leave out of the output entirely, including from the header.
+    pub drop_columns: Option<Columns>,
+    /// The order to write the fields of each record in, after dropping any
+    /// [`CleanseOptions::drop_columns`] and splitting any [`CleanseOptions::split_field`].
+    pub reorder_columns: Option<ColumnOrder>,
+    /// Split a column into two adjacent columns, before any
+    /// [`CleanseOptions::reorder_columns`].
+    pub split_field: Option<SplitField>,
+    /// Truncate any field longer than this many bytes, on a character boundary.
+    pub max_field_length: Option<usize>,
+    /// End fields truncated by [`CleanseOptions::max_field_length`] with `...`, still within the
+    /// maximum length.
+    pub truncate_ellipsis: bool,
+    /// Keep every [`FieldChange`] made in [`RunStats::changes`], and every [`FieldViolation`]
+    /// found in [`RunStats::violations`].
+    pub collect_changes: bool,
+    /// Replace any other ASCII control characters (0x01-0x1F and 0x7F) found inside of fields.
+    pub strip_control: bool,
+    /// Replace control characters with a `\xHH` escape of their value rather than with
+    /// [`CleanseOptions::replacement`], implies [`CleanseOptions::strip_control`].
+    pub hex_escape_control: bool,
+    /// Warn about any record that doesn't have exactly this many fields.
+    pub expected_fields: Option<usize>,
+    /// Warn about any record with fewer than this many fields.
+    pub min_fields: Option<usize>,
+    /// Warn about any record with more than this many fields.
+    pub max_fields: Option<usize>,
+    /// Abort the run on the first record without [`CleanseOptions::expected_fields`] fields, or
+    /// outside of [`CleanseOptions::min_fields`] and [`CleanseOptions::max_fields`], on a header
+    /// that names more than one column the same, and on an empty
+    /// [`CleanseOptions::required_fields`] field.
+    pub strict_fields: bool,
+    /// Skip records that can't be parsed, such as those with the wrong number of fields, instead
+    /// of returning an error.
+    pub skip_malformed: bool,
+    /// Split records that can't be parsed on the delimiter byte of their raw line, ignoring any
+    /// quoting, and cleanse them as usual rather than returning an error.
+    pub raw_split_malformed: bool,
+    /// Drop the empty last field of records that end with the delimiter, as spreadsheet exports
+    /// often do. A quoted empty last field can't be told apart from this, so it's dropped too.
+    pub strip_trailing_delimiter: bool,
+    /// Number of raw lines to discard from the start of the input before parsing it.
+    pub skip_lines: usize,
+    /// Text to replace any field that is empty after all other fixes with.
+    pub empty_replacement: Option<String>,
+    /// Rewrite numbers with currency symbols, grouping separators, or a decimal comma, such as
+    /// `$1,234.56` or `1 234,56`, as plain decimals like `1234.56`.
+    pub normalize_numbers: bool,
+    /// Records are terminated by `\0` rather than `\n` or `\r\n`, so `\n` and `\r` are left as is.
+    pub nul_terminated: bool,
+    /// The line ending to write after each record of [`OutputFormat::Csv`] output, overriding
+    /// [`CleanseOptions::nul_terminated`] for the output. Defaults to `\n`.
+    pub output_line_ending: Option<LineEnding>,
+    /// Replace each run of two or more ASCII whitespace characters with a single space.
+    pub collapse_whitespace: bool,
+    /// Stop after reading this many records, leaving the rest of the input unread.
+    pub max_records: Option<usize>,
+    /// Number of records to read and discard before cleansing any, the header is not counted.
+    pub skip_records: usize,
+    /// Pick the delimiter from the first line of the input out of [`DELIMITER_CANDIDATES`],
+    /// falling back to [`CleanseOptions::delimiter`] if none of them appear. The output delimiter
+    /// is changed to match unless it differs from [`CleanseOptions::delimiter`].
+    pub auto_detect: bool,
+    /// Drop any record with the same fields as the record read immediately before it.
+    pub dedup_adjacent: bool,
+    /// Columns that together identify a record, any record with the same fields in them as an
+    /// earlier record of the input is warned about. Every key seen is kept in memory.
+    pub unique_key: Option<Columns>,
+    /// Drop the records found to have a duplicate [`CleanseOptions::unique_key`].
+    pub drop_duplicates: bool,
+    /// Warn once more than this many distinct [`CleanseOptions::unique_key`] keys are kept in
+    /// memory.
+    pub unique_key_warn_threshold: usize,
+    /// Drop any record where every field is empty after cleansing.
+    pub remove_empty_records: bool,
+    /// Return [`CleanseError::EncodingError`] from [`run`] for any field that isn't valid UTF-8,
+    /// rather than replacing the invalid bytes. [`cleanse_field`] still replaces them, as it can't
+    /// fail.
+    pub no_encoding_fix: bool,
+    /// Leave any delimiters inside of fields as is, relying on quoting to keep them in the field.
+    pub no_delimiter_fix: bool,
+    /// Leave any `\n`, `\r\n`, or `\r` inside of fields as is, relying on quoting to keep them in
+    /// the field.
+    pub no_terminator_fix: bool,
+    /// Patterns that fields in a column must match after all other fixes, any that don't are
+    /// reported as a [`Violation::PatternNotMatched`] but left as is.
+    pub allow_patterns: Vec<AllowPattern>,
+    /// Columns that must not be empty after all other fixes, any empty fields in them are
+    /// reported as a [`Violation::RequiredFieldEmpty`], or abort the run with
+    /// [`CleanseOptions::strict_fields`].
+    pub required_fields: Option<Columns>,
+    /// Replacements for whole field values in a column, made after all other fixes except
+    /// [`CleanseOptions::empty_replacement`]. Values are mapped in columns left out of
+    /// [`CleanseOptions::columns`] too.
+    pub value_maps: Vec<ValueMap>,
+    /// Normalize every field to this Unicode normalization form, after all other fixes.
+    pub normalize_unicode: Option<UnicodeNormalization>,
+    /// Replace any field that isn't valid UTF-8 with the base64 encoding of it as read, instead of
+    /// replacing just the invalid bytes.
+    pub base64_non_utf8: bool,
+    /// Replace each byte that isn't part of valid UTF-8 with a `\xHH` escape of its value,
+    /// instead of with the replacement character.
+    pub hex_non_utf8: bool,
+    /// Start a new output after every this many records, only used by [`run_split`].
+    pub split_records: Option<usize>,
+    /// Add a leading column with the 1-based number of each record.
+    pub add_record_number: bool,
+    /// Header of the column added by [`CleanseOptions::add_record_number`].
+    pub record_number_header: String,
+    /// Name of the input, added as a trailing `_source_file` column of every record if set.
+    pub source_filename: Option<String>,
+    /// Read the input as fixed width fields of these widths instead of as delimited records.
+    pub fixed_width: Option<FixedWidths>,
+    /// Print each changed field to stderr as it was read and as it was written, see
+    /// [`FieldChange::diff`].
+    pub diff: bool,
+    /// Wait for more input at the end of the input, checking for more this often, rather than
+    /// ending the run. Each record is read and flushed to the output on its own so it can be seen
+    /// straight away. The run only ends at [`CleanseOptions::max_records`].
+    pub watch_interval: Option<Duration>,
+    /// Keep [`ColumnStats`] for each column of the output in [`RunStats::columns`].
+    pub statistics: bool,
+}
+
+impl Default for CleanseOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b'\t',
+            output_delimiter: b'\t',
+            replacement: String::from(" "),
+            delimiter_replacement: None,
+            terminator_replacement: None,
+            dry_run: false,
+            has_headers: false,
+            skip_header: false,
+            renames: vec![],
+            header_case: None,
+            batch_size: 1024,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            first_record_number: 0,
+            progress: false,
+            input_size: None,
+            regex_replacements: vec![],
+            quote_style: QuoteStyle::Necessary,
+            output_format: OutputFormat::Csv,
+            trim: false,
+            max_errors_per_record: None,
+            max_change_ratio: None,
+            quote: b'"',
+            columns: None,
+            drop_columns: None,
+            reorder_columns: None,
+            split_field: None,
+            max_field_length: None,
+            truncate_ellipsis: false,
+            collect_changes: false,
+            strip_control: false,
+            hex_escape_control: false,
+            expected_fields: None,
+            min_fields: None,
+            max_fields: None,
+            strict_fields: false,
+            skip_malformed: false,
+            raw_split_malformed: false,
+            strip_trailing_delimiter: false,
+            skip_lines: 0,
+            empty_replacement: None,
+            normalize_numbers: false,
+            nul_terminated: false,
+            output_line_ending: None,
+            collapse_whitespace: false,
+            max_records: None,
+            skip_records: 0,
+            auto_detect: false,
+            dedup_adjacent: false,
+            unique_key: None,
+            drop_duplicates: false,
+            unique_key_warn_threshold: DEFAULT_UNIQUE_KEY_WARN_THRESHOLD,
+            remove_empty_records: false,
+            no_encoding_fix: false,
+            no_delimiter_fix: false,
+            no_terminator_fix: false,
+            allow_patterns: vec![],
+            required_fields: None,
+            value_maps: vec![],
+            normalize_unicode: None,
+            base64_non_utf8: false,
+            hex_non_utf8: false,
+            split_records: None,
+            add_record_number: false,
+            record_number_header: String::from("_record_number"),
+            source_filename: None,
+            fixed_width: None,
+            diff: false,
+            watch_interval: None,
+            statistics: false,
+        }
+    }
+}
+
+/// Generate a builder method for each of the given [`CleanseOptions`] fields.
+macro_rules! builder_methods {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        $(
+            #[doc = concat!("Set [`CleanseOptions::", stringify!($field), "`].")]
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+/// Builder methods for each option, to be chained onto [`CleanseOptions::default`].
+///
+/// ```
+/// use cleanse::CleanseOptions;
+///
+/// let opts = CleanseOptions::default()
+///     .delimiter(b',')
+///     .output_delimiter(b',')
+///     .trim(true)
+///     .max_field_length(Some(255));
+/// assert_eq!(opts.max_field_length, Some(255));
+/// ```
+impl CleanseOptions {
+    builder_methods! {
+        delimiter: u8,
+        output_delimiter: u8,
+        replacement: String,
+        delimiter_replacement: Option<String>,
+        terminator_replacement: Option<String>,
+        dry_run: bool,
+        has_headers: bool,
+        skip_header: bool,
+        renames: Vec<ColumnRename>,
+        header_case: Option<HeaderCase>,
+        batch_size: usize,
+        threads: usize,
+        first_record_number: usize,
+        progress: bool,
+        input_size: Option<u64>,
+        regex_replacements: Vec<RegexReplace>,
+        quote_style: QuoteStyle,
+        output_format: OutputFormat,
+        trim: bool,
+        max_errors_per_record: Option<usize>,
+        max_change_ratio: Option<f64>,
+        quote: u8,
+        columns: Option<Columns>,
+        drop_columns: Option<Columns>,
+        reorder_columns: Option<ColumnOrder>,
+        split_field: Option<SplitField>,
+        max_field_length: Option<usize>,
+        truncate_ellipsis: bool,
+        collect_changes: bool,
+        strip_control: bool,
+        hex_escape_control: bool,
+        expected_fields: Option<usize>,
+        min_fields: Option<usize>,
+        max_fields: Option<usize>,
+        strict_fields: bool,
+        skip_malformed: bool,
+        raw_split_malformed: bool,
+        strip_trailing_delimiter: bool,
+        skip_lines: usize,
+        empty_replacement: Option<String>,
+        normalize_numbers: bool,
+        nul_terminated: bool,
+        output_line_ending: Option<LineEnding>,
+        collapse_whitespace: bool,
+        max_records: Option<usize>,
+        skip_records: usize,
+        auto_detect: bool,
+        dedup_adjacent: bool,
+        unique_key: Option<Columns>,
+        drop_duplicates: bool,
+        unique_key_warn_threshold: usize,
+        remove_empty_records: bool,
+        no_encoding_fix: bool,
+        no_delimiter_fix: bool,
+        no_terminator_fix: bool,
+        allow_patterns: Vec<AllowPattern>,
+        required_fields: Option<Columns>,
+        value_maps: Vec<ValueMap>,
+        normalize_unicode: Option<UnicodeNormalization>,
+        base64_non_utf8: bool,
+        hex_non_utf8: bool,
+        split_records: Option<usize>,
+        add_record_number: bool,
+        record_number_header: String,
+        source_filename: Option<String>,
+        fixed_width: Option<FixedWidths>,
+        diff: bool,
+        watch_interval: Option<Duration>,
+        statistics: bool,
+    }
+}
+
+impl CleanseOptions {
+    /// Whether the number of fields in each record is checked, so records may differ in length.
+    pub fn checks_field_count(&self) -> bool {
+        self.expected_fields.is_some() || self.min_fields.is_some() || self.max_fields.is_some()
+    }
+}
+
+/// A selection of 1-based column indices, as parsed by [`parse_columns`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Columns {
+    /// The columns selected by single indices or bounded ranges.
+    pub indices: BTreeSet<usize>,
+    /// The first column of an open ended range such as `30-`, which selects every column from it
+    /// to the end of the record.
+    pub open_from: Option<usize>,
+}
+
+impl Columns {
+    /// Check if the 1-based `column` is selected.
+    #[inline]
+    pub fn contains(&self, column: usize) -> bool {
+        self.open_from.is_some_and(|from| column >= from) || self.indices.contains(&column)
+    }
+
+    /// The number of fields a record needs for every selected column to be present in it.
+    pub fn min_fields(&self) -> Option<usize> {
+        self.indices.iter().next_back().copied().max(self.open_from)
+    }
+}
+
+/// Parse a comma separated list of 1-based column indices and cut style ranges, i.e. `2,4` or
+/// `1-10,20,30-`. A range without a start begins at the first column, and a range without an end
+/// continues to the last column of each record.
+pub fn parse_columns(s: &str) -> Result<Columns, String> {
+    let parse_index = |index: &str| match index.trim().parse::<usize>() {
+        Ok(0) => Err(String::from("Column indices start at 1")),
+        Ok(index) => Ok(index),
+        Err(_) => Err(format!("Invalid column index {:?}", index)),
+    };
+    let mut columns = Columns::default();
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start = if start.trim().is_empty() {
+                    1
+                } else {
+                    parse_index(start)?
+                };
+                if end.trim().is_empty() {
+                    columns.open_from =
+                        Some(columns.open_from.map_or(start, |from| from.min(start)));
+                } else {
+                    let end = parse_index(end)?;
+                    if end < start {
+                        return Err(format!("Invalid column range {:?}", part));
+                    }
+                    columns.indices.extend(start..=end);
+                }
+            }
+            None => {
+                columns.indices.insert(parse_index(part)?);
+            }
+        }
+    }
+    Ok(columns)
+}
+
+/// An order to write fields in, parsed from a comma separated list of 1-based column indices
+/// such as `3,1,2`, which must name every column exactly once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnOrder {
+    /// The 1-based input column to write at each position of the output.
+    pub order: Vec<usize>,
+}
+
+impl ColumnOrder {
+    /// Rearrange the fields of `record`, which must have as many fields as there are columns.
+    pub fn apply(&self, record: &ByteRecord) -> ByteRecord {
+        self.order
+            .iter()
+            .map(|&column| &record[column - 1])
+            .collect()
+    }
+}
+
+impl FromStr for ColumnOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut order = vec![];
+        for index in s.split(',') {
+            match index.trim().parse::<usize>() {
+                Ok(0) => return Err(String::from("Column indices start at 1")),
+                Ok(index) => order.push(index),
+                Err(_) => return Err(format!("Invalid column index {:?}", index)),
+            }
+        }
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(format!("Column {} is listed more than once", pair[0]));
+        }
+        if let Some(missing) = (1..)
+            .zip(&sorted)
+            .find(|&(expected, &column)| column != expected)
+        {
+            return Err(format!("Column {} is missing", missing.0));
+        }
+        Ok(Self { order })
+    }
+}
+
+/// A column to split in two on the first occurrence of a separator, parsed from
+/// `column:separator:first_name:second_name` where the column is 1-based and the names are the
+/// headers of the two new columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitField {
+    pub column: usize,
+    pub separator: String,
+    pub first_name: String,
+    pub second_name: String,
+}
+
+impl SplitField {
+    /// Split `field` around the first separator, or into itself and an empty field without one.
+    pub fn split<'a>(&self, field: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        match field.find(&self.separator) {
+            Some(start) => (&field[..start], &field[start + self.separator.len()..]),
+            None => (field, b""),
+        }
+    }
+}
+
+impl FromStr for SplitField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "Expected column:separator:first_name:second_name, found {:?}",
+                s
+            )
+        };
+        let (column, rest) = s.split_once(':').ok_or_else(error)?;
+        let column = match column.parse() {
+            Ok(column) if column > 0 => column,
+            _ => return Err(format!("Invalid column {:?}, columns start at 1", column)),
+        };
+        // The separator may itself contain a `:`, but the names can't
+        let mut parts = rest.rsplitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(second_name), Some(first_name), Some(separator)) if !separator.is_empty() => {
+                Ok(Self {
+                    column,
+                    separator: separator.to_string(),
+                    first_name: first_name.to_string(),
+                    second_name: second_name.to_string(),
+                })
+            }
+            _ => Err(error()),
+        }
+    }
+}
+
+/// Parse a [`QuoteStyle`] from one of `always`, `necessary`, `non-numeric`, or `never`.
+pub fn parse_quote_style(s: &str) -> Result<QuoteStyle, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "always" => Ok(QuoteStyle::Always),
+        "necessary" => Ok(QuoteStyle::Necessary),
+        "non-numeric" | "nonnumeric" => Ok(QuoteStyle::NonNumeric),
+        "never" => Ok(QuoteStyle::Never),
+        _ => Err(format!(
+            "Unknown quote style {:?}, expected one of always, necessary, non-numeric, never",
+            s
+        )),
+    }
+}
+
+/// A regex substitution to apply to fields, parsed from `pattern=replacement`.
+///
+/// The replacement may refer to capture groups from the pattern, i.e. `$1`.
+#[derive(Debug, Clone)]
+pub struct RegexReplace {
+    pub regex: Regex,
+    pub replacement: String,
+}
+
+impl FromStr for RegexReplace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected pattern=replacement, found {:?}", s))?;
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(Self {
+            regex,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+/// A pattern that every field in a column must match, parsed from `column:pattern` where the
+/// column is 1-based.
+#[derive(Debug, Clone)]
+pub struct AllowPattern {
+    pub column: usize,
+    pub regex: Regex,
+}
+
+impl FromStr for AllowPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, pattern) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected column:pattern, found {:?}", s))?;
+        let column = match column.parse() {
+            Ok(column) if column > 0 => column,
+            _ => return Err(format!("Invalid column {:?}, columns start at 1", column)),
+        };
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(Self { column, regex })
+    }
+}
+
+/// A replacement for a whole field value in a column, parsed from `column:old=new` where the
+/// column is 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueMap {
+    pub column: usize,
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for ValueMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, mapping) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected column:old=new, found {:?}", s))?;
+        let column = match column.parse() {
+            Ok(column) if column > 0 => column,
+            _ => return Err(format!("Invalid column {:?}, columns start at 1", column)),
+        };
+        let (from, to) = mapping
+            .split_once('=')
+            .ok_or_else(|| format!("Expected column:old=new, found {:?}", s))?;
+        Ok(Self {
+            column,
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+/// A new name for a header column, parsed from `old_name:new_name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnRename {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for ColumnRename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((from, to)) if !from.is_empty() => Ok(Self {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            }),
+            _ => Err(format!("Expected old_name:new_name, found {:?}", s)),
+        }
+    }
+}
+
+/// The case to convert header names to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderCase {
+    Lower,
+    Upper,
+}
+
+impl HeaderCase {
+    /// `name` converted to this case.
+    pub fn apply(self, name: &[u8]) -> Vec<u8> {
+        match self {
+            HeaderCase::Lower => name.to_lowercase(),
+            HeaderCase::Upper => name.to_uppercase(),
+        }
+    }
+}
+
+/// Counts of each kind of change made over the course of a [`run`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RunStats {
+    /// Every change made, only kept when [`CleanseOptions::collect_changes`] is set.
+    pub changes: Vec<FieldChange>,
+    /// Every violation found, only kept when [`CleanseOptions::collect_changes`] is set.
+    pub violations: Vec<FieldViolation>,
+    pub records: u64,
+    pub records_dropped: u64,
+    pub field_count_mismatches: u64,
+    pub field_count_under: u64,
+    pub field_count_over: u64,
+    pub malformed_records: u64,
+    /// Records that couldn't be parsed and were split on the delimiter instead.
+    pub raw_split_records: u64,
+    pub records_skipped: u64,
+    pub adjacent_duplicates_dropped: u64,
+    /// Records with the same [`CleanseOptions::unique_key`] as an earlier record.
+    pub duplicate_keys: u64,
+    /// Records with at least one change or violation that were written to
+    /// [`SideOutputs::error_output`].
+    pub records_written_to_error_output: u64,
+    pub empty_records_dropped: u64,
+    /// Fields read across all of the records read, counted with [`RunStats::records`] so that
+    /// dropped records are included in both.
+    pub fields: u64,
+    /// Records with at least one changed field.
+    pub records_changed: u64,
+    /// Fields with at least one change.
+    pub fields_changed: u64,
+    pub null_byte_replacements: u64,
+    pub delimiter_replacements: u64,
+    pub terminator_replacements: u64,
+    pub carriage_return_replacements: u64,
+    pub encoding_fixes: u64,
+    pub regex_replacements: u64,
+    pub whitespace_trims: u64,
+    pub field_truncations: u64,
+    pub control_char_replacements: u64,
+    pub empty_field_replacements: u64,
+    pub numeric_normalizations: u64,
+    pub whitespace_collapses: u64,
+    /// Time spent on the run, from reading the first line to flushing all output.
+    pub elapsed: Duration,
+    /// Fields that didn't match their [`CleanseOptions::allow_patterns`].
+    pub pattern_violations: u64,
+    pub base64_encodings: u64,
+    pub hex_encodings: u64,
+    pub value_maps: u64,
+    /// Empty fields in [`CleanseOptions::required_fields`].
+    pub required_field_violations: u64,
+    pub unicode_normalizations: u64,
+    /// Statistics for each column of the output in order, only kept when
+    /// [`CleanseOptions::statistics`] is set.
+    pub columns: Vec<ColumnStats>,
+}
+
+/// The width in characters of the longest bar drawn by [`RunStats::write_change_histogram`].
+pub const HISTOGRAM_WIDTH: usize = 40;
+
+/// Statistics about the fields written to a single column.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ColumnStats {
+    /// The header of the column, if there is one.
+    pub name: Option<String>,
+    /// The number of fields in the column that aren't empty.
+    pub non_empty: u64,
+    /// The length in bytes of the longest field in the column.
+    pub max_length: usize,
+}
+
+impl RunStats {
+    /// Count a single change.
+    #[inline]
+    pub fn add(&mut self, change: CleanseChanges) {
+        match change {
+            CleanseChanges::NullByteReplacement => self.null_byte_replacements += 1,
+            CleanseChanges::DelimiterReplacement => self.delimiter_replacements += 1,
+            CleanseChanges::TerminatorReplacement => self.terminator_replacements += 1,
+            CleanseChanges::CarriageReturnReplacement => self.carriage_return_replacements += 1,
+            CleanseChanges::FixedEncoding => self.encoding_fixes += 1,
+            CleanseChanges::RegexReplacement => self.regex_replacements += 1,
+            CleanseChanges::WhitespaceTrimmed => self.whitespace_trims += 1,
+            CleanseChanges::FieldTruncated => self.field_truncations += 1,
+            CleanseChanges::ControlCharReplacement => self.control_char_replacements += 1,
+            CleanseChanges::EmptyFieldReplaced => self.empty_field_replacements += 1,
+            CleanseChanges::NumericNormalized => self.numeric_normalizations += 1,
+            CleanseChanges::WhitespaceCollapsed => self.whitespace_collapses += 1,
+            CleanseChanges::Base64Encoded => self.base64_encodings += 1,
+            CleanseChanges::HexEncoded => self.hex_encodings += 1,
+            CleanseChanges::ValueMapped => self.value_maps += 1,
+            CleanseChanges::UnicodeNormalized => self.unicode_normalizations += 1,
+        }
+    }
+
+    /// Count a single violation.
+    #[inline]
+    pub fn add_violation(&mut self, violation: Violation) {
+        match violation {
+            Violation::PatternNotMatched => self.pattern_violations += 1,
+            Violation::RequiredFieldEmpty => self.required_field_violations += 1,
+        }
+    }
+
+    /// Add the counts from another run to this one.
+    pub fn merge(&mut self, other: &RunStats) {
+        self.changes.extend(other.changes.iter().cloned());
+        self.violations.extend(other.violations.iter().cloned());
+        self.records += other.records;
+        self.records_dropped += other.records_dropped;
+        self.field_count_mismatches += other.field_count_mismatches;
+        self.field_count_under += other.field_count_under;
+        self.field_count_over += other.field_count_over;
+        self.malformed_records += other.malformed_records;
+        self.raw_split_records += other.raw_split_records;
+        self.records_skipped += other.records_skipped;
+        self.adjacent_duplicates_dropped += other.adjacent_duplicates_dropped;
+        self.duplicate_keys += other.duplicate_keys;
+        self.records_written_to_error_output += other.records_written_to_error_output;
+        self.empty_records_dropped += other.empty_records_dropped;
+        self.fields += other.fields;
+        self.records_changed += other.records_changed;
+        self.fields_changed += other.fields_changed;
+        self.null_byte_replacements += other.null_byte_replacements;
+        self.delimiter_replacements += other.delimiter_replacements;
+        self.terminator_replacements += other.terminator_replacements;
+        self.carriage_return_replacements += other.carriage_return_replacements;
+        self.encoding_fixes += other.encoding_fixes;
+        self.regex_replacements += other.regex_replacements;
+        self.whitespace_trims += other.whitespace_trims;
+        self.field_truncations += other.field_truncations;
+        self.control_char_replacements += other.control_char_replacements;
+        self.empty_field_replacements += other.empty_field_replacements;
+        self.numeric_normalizations += other.numeric_normalizations;
+        self.whitespace_collapses += other.whitespace_collapses;
+        self.elapsed += other.elapsed;
+        self.pattern_violations += other.pattern_violations;
+        self.base64_encodings += other.base64_encodings;
+        self.hex_encodings += other.hex_encodings;
+        self.value_maps += other.value_maps;
+        self.required_field_violations += other.required_field_violations;
+        self.unicode_normalizations += other.unicode_normalizations;
+        for (i, other) in other.columns.iter().enumerate() {
+            match self.columns.get_mut(i) {
+                Some(column) => {
+                    column.name = column.name.take().or_else(|| other.name.clone());
+                    column.non_empty += other.non_empty;
+                    column.max_length = column.max_length.max(other.max_length);
+                }
+                None => self.columns.push(other.clone()),
+            }
+        }
+    }
+
+    /// Name each column in [`RunStats::columns`] after its field in `header`.
+    fn name_columns(&mut self, header: &ByteRecord) {
+        if self.columns.len() < header.len() {
+            self.columns.resize_with(header.len(), ColumnStats::default);
+        }
+        for (column, name) in self.columns.iter_mut().zip(header) {
+            column.name = Some(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+
+    /// Count the fields of a written record in [`RunStats::columns`].
+    fn count_columns(&mut self, record: &ByteRecord) {
+        if self.columns.len() < record.len() {
+            self.columns.resize_with(record.len(), ColumnStats::default);
+        }
+        for (column, field) in self.columns.iter_mut().zip(record) {
+            if !field.is_empty() {
+                column.non_empty += 1;
+            }
+            column.max_length = column.max_length.max(field.len());
+        }
+    }
+
+    /// Write each kind of change that was made with its count, most frequent first, and a bar
+    /// scaled so that the most frequent is [`HISTOGRAM_WIDTH`] characters wide.
+    pub fn write_change_histogram<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut counts: Vec<(CleanseChanges, u64)> = self
+            .change_counts()
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        counts.sort_by_key(|(_, count)| Reverse(*count));
+        let max_count = counts.first().map_or(0, |(_, count)| *count);
+        let name_width = counts
+            .iter()
+            .map(|(change, _)| change.name().len())
+            .max()
+            .unwrap_or(0);
+        let count_width = max_count.to_string().len();
+        for (change, count) in counts {
+            // Rounded, but never so small that a change that was made has no bar
+            let bar = ((count * HISTOGRAM_WIDTH as u64 + max_count / 2) / max_count).max(1);
+            writeln!(
+                writer,
+                "{:<name_width$} {:>count_width$} {}",
+                change.name(),
+                count,
+                "#".repeat(bar as usize),
+                name_width = name_width,
+                count_width = count_width
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write [`RunStats::columns`] as a TSV table, naming each column by its header or else by its
+    /// 0-based index.
+    pub fn write_column_stats<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "column\tnon_empty\tmax_length")?;
+        for (i, column) in self.columns.iter().enumerate() {
+            match &column.name {
+                Some(name) => write!(writer, "{}", name)?,
+                None => write!(writer, "{}", i)?,
+            }
+            writeln!(writer, "\t{}\t{}", column.non_empty, column.max_length)?;
+        }
+        Ok(())
+    }
+
+    /// The number of times each kind of change was made.
+    pub fn change_counts(&self) -> Vec<(CleanseChanges, u64)> {
+        vec![
+            (
+                CleanseChanges::NullByteReplacement,
+                self.null_byte_replacements,
+            ),
+            (
+                CleanseChanges::DelimiterReplacement,
+                self.delimiter_replacements,
+            ),
+            (
+                CleanseChanges::TerminatorReplacement,
+                self.terminator_replacements,
+            ),
+            (
+                CleanseChanges::CarriageReturnReplacement,
+                self.carriage_return_replacements,
+            ),
+            (CleanseChanges::FixedEncoding, self.encoding_fixes),
+            (CleanseChanges::RegexReplacement, self.regex_replacements),
+            (CleanseChanges::WhitespaceTrimmed, self.whitespace_trims),
+            (CleanseChanges::FieldTruncated, self.field_truncations),
+            (
+                CleanseChanges::ControlCharReplacement,
+                self.control_char_replacements,
+            ),
+            (
+                CleanseChanges::EmptyFieldReplaced,
+                self.empty_field_replacements,
+            ),
+            (
+                CleanseChanges::NumericNormalized,
+                self.numeric_normalizations,
+            ),
+            (
+                CleanseChanges::WhitespaceCollapsed,
+                self.whitespace_collapses,
+            ),
+            (CleanseChanges::Base64Encoded, self.base64_encodings),
+            (CleanseChanges::HexEncoded, self.hex_encodings),
+            (CleanseChanges::ValueMapped, self.value_maps),
+            (
+                CleanseChanges::UnicodeNormalized,
+                self.unicode_normalizations,
+            ),
+        ]
+    }
+
+    /// The total number of changes made.
+    pub fn total_changes(&self) -> u64 {
+        self.change_counts().iter().map(|(_, count)| count).sum()
+    }
+
+    /// Whether any change at all was made.
+    pub fn has_changes(&self) -> bool {
+        self.total_changes() > 0
+    }
+
+    /// A machine readable summary of the run, for monitoring.
+    pub fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "total_records": self.records,
+            "total_fields": self.fields,
+            "records_changed": self.records_changed,
+            "fields_changed": self.fields_changed,
+            "delimiter_replacements": self.delimiter_replacements,
+            "terminator_replacements": self.terminator_replacements,
+            "encoding_fixes": self.encoding_fixes,
+            "elapsed_seconds": self.elapsed.as_secs_f64(),
+        })
+    }
+}
+
+/// A single change made to a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    /// The record number the field is in.
+    pub record: usize,
+    /// The 0-based field number within the record.
+    pub field: usize,
+    /// The kind of change made.
+    pub kind: CleanseChanges,
+    /// The field as it was read.
+    pub original: Vec<u8>,
+    /// The field after all changes were made.
+    pub cleaned: String,
+}
+
+impl FieldChange {
+    /// A line showing the field before and after it was cleansed, such as
+    /// `[record 3, field 1] "a,b" -> "a b"`.
+    pub fn diff(&self) -> String {
+        format!(
+            "[record {}, field {}] {:?} -> {:?}",
+            self.record,
+            self.field,
+            self.original.as_bstr(),
+            self.cleaned
+        )
+    }
+}
+
+/// A single violation found in a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldViolation {
+    /// The record number the field is in.
+    pub record: usize,
+    /// The 0-based field number within the record.
+    pub field: usize,
+    /// The kind of violation found.
+    pub kind: Violation,
+}
+
+/// Whether `byte` is an ASCII control character other than null.
+#[inline]
+fn is_control(byte: u8) -> bool {
+    matches!(byte, 0x01..=0x1F | 0x7F)
+}
+
+/// Cleanse a single field, returning the cleaned field and one [`FieldChange`] for each kind of
+/// change that was made to it.
+#[inline]
+pub fn cleanse_field(
+    bytes: &[u8],
+    opts: &CleanseOptions,
+    record_number: usize,
+    field_number: usize,
+) -> (String, Vec<FieldChange>) {
+    let replacement = opts.replacement.as_bytes();
+    let delimiter_replacement = opts
+        .delimiter_replacement
+        .as_ref()
+        .map_or(replacement, |r| r.as_bytes());
+    let terminator_replacement = opts
+        .terminator_replacement
+        .as_ref()
+        .map_or(replacement, |r| r.as_bytes());
+    let mut changes = vec![];
+    // Replace any null bytes
+    let null_fixed = bytes.replace("\0", replacement);
+    if null_fixed != bytes {
+        changes.push(CleanseChanges::NullByteReplacement);
+    }
+    // Replace any delimiter or terminator characters
+    let delim_fixed = if opts.no_delimiter_fix {
+        null_fixed
+    } else {
+        let delim_fixed = null_fixed.replace([opts.delimiter], delimiter_replacement);
+        if delim_fixed != null_fixed {
+            changes.push(CleanseChanges::DelimiterReplacement);
+        }
+        delim_fixed
+    };
+    // When records are `\0` terminated the null byte replacement has already removed terminators
+    let term_fixed = if opts.nul_terminated || opts.no_terminator_fix {
+        delim_fixed
+    } else {
+        // `\r\n` must be replaced before either of its halves
+        let cr_fixed = delim_fixed
+            .replace("\r\n", terminator_replacement)
+            .replace("\r", terminator_replacement);
+        if cr_fixed != delim_fixed {
+            changes.push(CleanseChanges::CarriageReturnReplacement);
+        }
+        let term_fixed = cr_fixed.replace("\n", terminator_replacement);
+        if term_fixed != cr_fixed {
+            changes.push(CleanseChanges::TerminatorReplacement);
+        }
+        term_fixed
+    };
+    // Delimiters, terminators and nulls have already been replaced, which leaves only the other
+    // control characters to replace here. Any that were deliberately left in are kept.
+    let is_stripped = |byte: u8| {
+        is_control(byte)
+            && !(opts.no_delimiter_fix && byte == opts.delimiter)
+            && !(opts.no_terminator_fix && (byte == b'\n' || byte == b'\r'))
+    };
+    let strip_control = opts.strip_control || opts.hex_escape_control;
+    let control_fixed = if strip_control && term_fixed.iter().any(|&b| is_stripped(b)) {
+        changes.push(CleanseChanges::ControlCharReplacement);
+        let mut fixed = Vec::with_capacity(term_fixed.len());
+        for &byte in &term_fixed {
+            if is_stripped(byte) && opts.hex_escape_control {
+                fixed.extend_from_slice(format!("\\x{:02X}", byte).as_bytes());
+            } else if is_stripped(byte) {
+                fixed.extend_from_slice(replacement);
+            } else {
+                fixed.push(byte);
+            }
+        }
+        fixed
+    } else {
+        term_fixed
+    };
+    // Fix encoding
+    let mut str = match control_fixed.into_string() {
+        Ok(new_string) => new_string,
+        // The whole field is replaced, so none of the other changes made so far apply
+        Err(_) if opts.base64_non_utf8 => {
+            changes.clear();
+            changes.push(CleanseChanges::Base64Encoded);
+            base64::encode(bytes)
+        }
+        Err(e) if opts.hex_non_utf8 => {
+            changes.push(CleanseChanges::HexEncoded);
+            let bytes = e.into_vec();
+            let mut escaped = String::with_capacity(bytes.len() + 8);
+            for chunk in bytes.utf8_chunks() {
+                escaped.push_str(chunk.valid());
+                for byte in chunk.invalid() {
+                    escaped.push_str(&format!("\\x{:02X}", byte));
+                }
+            }
+            escaped
+        }
+        Err(e @ bstr::FromUtf8Error { .. }) => {
+            changes.push(CleanseChanges::FixedEncoding);
+            e.into_vec().into_string_lossy()
+        }
+    };
+    // Apply any user supplied substitutions, in order
+    for regex_replace in &opts.regex_replacements {
+        if let Cow::Owned(replaced) = regex_replace
+            .regex
+            .replace_all(&str, regex_replace.replacement.as_str())
+        {
+            if replaced != str {
+                changes.push(CleanseChanges::RegexReplacement);
+                str = replaced;
+            }
+        }
+    }
+    // Trim last so that it doesn't interact with any of the replacements
+    if opts.trim {
+        let trimmed = str.trim_matches(|c: char| c.is_ascii_whitespace());
+        if trimmed.len() != str.len() {
+            changes.push(CleanseChanges::WhitespaceTrimmed);
+            str = trimmed.to_string();
+        }
+    }
+    // After trimming so that whitespace at the edges is only counted as trimmed
+    if opts.collapse_whitespace {
+        if let Some(collapsed) = collapse_whitespace(&str) {
+            changes.push(CleanseChanges::WhitespaceCollapsed);
+            str = collapsed;
+        }
+    }
+    if opts.normalize_numbers {
+        if let Some(normalized) = normalize_number(&str) {
+            if normalized != str {
+                changes.push(CleanseChanges::NumericNormalized);
+                str = normalized;
+            }
+        }
+    }
+    if let Some(max_length) = opts.max_field_length {
+        if str.len() > max_length {
+            changes.push(CleanseChanges::FieldTruncated);
+            let ellipsis = if opts.truncate_ellipsis {
+                &"..."[..max_length.min(3)]
+            } else {
+                ""
+            };
+            str.truncate(floor_char_boundary(&str, max_length - ellipsis.len()));
+            // Truncating may have left whitespace at the new end that trimming should remove
+            if opts.trim {
+                let trimmed_len = str
+                    .trim_end_matches(|c: char| c.is_ascii_whitespace())
+                    .len();
+                str.truncate(trimmed_len);
+            }
+            str.push_str(ellipsis);
+        }
+    }
+    if let Some(value_map) = find_value_map(opts, str.as_bytes(), field_number) {
+        changes.push(CleanseChanges::ValueMapped);
+        str = value_map.to.clone();
+    }
+    if let Some(empty_replacement) = &opts.empty_replacement {
+        if str.is_empty() {
+            changes.push(CleanseChanges::EmptyFieldReplaced);
+            str = empty_replacement.clone();
+        }
+    }
+    if let Some(normalization) = opts.normalize_unicode {
+        if let Cow::Owned(normalized) = normalization.apply(&str) {
+            changes.push(CleanseChanges::UnicodeNormalized);
+            str = normalized;
+        }
+    }
+    let changes = changes
+        .into_iter()
+        .map(|kind| FieldChange {
+            record: record_number,
+            field: field_number,
+            kind,
+            original: bytes.to_vec(),
+            cleaned: str.clone(),
+        })
+        .collect();
+    (str, changes)
+}
+
+/// Replace each run of two or more ASCII whitespace characters in `s` with a single space,
+/// returning `None` if there are none.
+fn collapse_whitespace(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    if !bytes
+        .windows(2)
+        .any(|pair| pair[0].is_ascii_whitespace() && pair[1].is_ascii_whitespace())
+    {
+        return None;
+    }
+    let mut collapsed = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_whitespace()) {
+        collapsed.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        // A lone whitespace character is kept as is
+        collapsed.push_str(if len == 1 { &rest[..1] } else { " " });
+        rest = &rest[len..];
+    }
+    collapsed.push_str(rest);
+    Some(collapsed)
+}
+
+/// Normalize a number written with currency symbols, spaces, grouping separators, or a decimal
+/// comma to a plain decimal, returning `None` if `s` doesn't look like a number.
+///
+/// When both `.` and `,` are present `.` is taken to be the decimal separator. A lone `,` is
+/// taken to be a grouping separator only if it's followed by exactly three digits, as in `1,234`.
+fn normalize_number(s: &str) -> Option<String> {
+    let mut negative = false;
+    let mut digits = String::with_capacity(s.len());
+    for c in s.trim().chars() {
+        match c {
+            '0'..='9' | '.' | ',' => digits.push(c),
+            '-' | '+' if digits.is_empty() && !negative => negative = c == '-',
+            '$' | '€' | '£' | '¥' | ' ' | '\u{a0}' | '\u{202f}' => {}
+            _ => return None,
+        }
+    }
+    if !digits.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let decimal = if digits.contains('.') {
+        digits.replace(',', "")
+    } else {
+        match digits.split_once(',') {
+            Some((whole, fraction))
+                if !fraction.contains(',')
+                    && (fraction.len() != 3 || whole.is_empty() || whole == "0") =>
+            {
+                format!("{}.{}", whole, fraction)
+            }
+            _ => digits.replace(',', ""),
+        }
+    };
+    if decimal.matches('.').count() > 1 || !decimal.parse::<f64>().ok()?.is_finite() {
+        return None;
+    }
+    Some(if negative {
+        format!("-{}", decimal)
+    } else {
+        decimal
+    })
+}
+
+/// Find the largest index of a character boundary in `s` that is less than or equal to `index`.
+#[inline]
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    if index >= s.len() {
+        return s.len();
+    }
+    (0..=index)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// The mapping of `field` in the 0-based `field_number` column out of
+/// [`CleanseOptions::value_maps`]. Only whole values are mapped, and only the first mapping of a
+/// value applies.
+fn find_value_map<'a>(
+    opts: &'a CleanseOptions,
+    field: &[u8],
+    field_number: usize,
+) -> Option<&'a ValueMap> {
+    opts.value_maps.iter().find(|value_map| {
+        value_map.column == field_number + 1 && value_map.from.as_bytes() == field
+    })
+}
+
+/// Check `field` as it will be written against [`CleanseOptions::allow_patterns`] and
+/// [`CleanseOptions::required_fields`], adding any violations to `violations`.
+fn check_field(
+    field: &[u8],
+    opts: &CleanseOptions,
+    record_number: usize,
+    field_number: usize,
+    violations: &mut Vec<FieldViolation>,
+) {
+    let column = field_number + 1;
+    let mut violation = |kind| {
+        violations.push(FieldViolation {
+            record: record_number,
+            field: field_number,
+            kind,
+        })
+    };
+    // Only one violation is reported however many of the column's patterns aren't matched
+    if opts
+        .allow_patterns
+        .iter()
+        .filter(|allow_pattern| allow_pattern.column == column)
+        .any(|allow_pattern| {
+            !allow_pattern
+                .regex
+                .is_match(&String::from_utf8_lossy(field))
+        })
+    {
+        violation(Violation::PatternNotMatched);
+    }
+    if field.is_empty()
+        && opts
+            .required_fields
+            .as_ref()
+            .is_some_and(|columns| columns.contains(column))
+    {
+        violation(Violation::RequiredFieldEmpty);
+    }
+}
+
+/// Cleanse every field of a record, returning the cleansed record, all changes made, and all
+/// violations found, each in field order.
+fn cleanse_record(
+    record: &ByteRecord,
+    opts: &CleanseOptions,
+    record_number: usize,
+) -> (ByteRecord, Vec<FieldChange>, Vec<FieldViolation>) {
+    let mut cleansed = ByteRecord::with_capacity(record.as_slice().len(), record.len());
+    let mut record_changes = vec![];
+    let mut violations = vec![];
+    for (field_number, field) in record.iter().enumerate() {
+        if is_dropped(opts, field_number) {
+            continue;
+        }
+        let selected = opts
+            .columns
+            .as_ref()
+            .is_none_or(|columns| columns.contains(field_number + 1));
+        let field = if selected {
+            let (field, changes) = cleanse_field(field, opts, record_number, field_number);
+            record_changes.extend(changes);
+            Cow::Owned(field.into_bytes())
+        } else {
+            // Values are still mapped in columns that are otherwise left as is
+            match find_value_map(opts, field, field_number) {
+                Some(value_map) => {
+                    record_changes.push(FieldChange {
+                        record: record_number,
+                        field: field_number,
+                        kind: CleanseChanges::ValueMapped,
+                        original: field.to_vec(),
+                        cleaned: value_map.to.clone(),
+                    });
+                    Cow::Borrowed(value_map.to.as_bytes())
+                }
+                None => Cow::Borrowed(field),
+            }
+        };
+        check_field(&field, opts, record_number, field_number, &mut violations);
+        // The header always gets the split column's names, so it's split even if not selected
+        match &opts.split_field {
+            Some(split_field) if split_field.column == field_number + 1 => {
+                let (first, second) = split_field.split(&field);
+                cleansed.push_field(first);
+                cleansed.push_field(second);
+            }
+            _ => cleansed.push_field(&field),
+        }
+    }
+    (cleansed, record_changes, violations)
+}
+
+/// Whether the 0-based `field_number` is left out of the output by
+/// [`CleanseOptions::drop_columns`].
+#[inline]
+fn is_dropped(opts: &CleanseOptions, field_number: usize) -> bool {
+    opts.drop_columns
+        .as_ref()
+        .is_some_and(|columns| columns.contains(field_number + 1))
+}
+
+/// Cleanse a batch of records across `opts.threads` threads, preserving record order.
+fn cleanse_batch(
+    records: &[ByteRecord],
+    opts: &CleanseOptions,
+    first_record_number: usize,
+) -> Vec<(ByteRecord, Vec<FieldChange>, Vec<FieldViolation>)> {
+    let threads = opts.threads.max(1);
+    if threads == 1 || records.len() < 2 {
+        return records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| cleanse_record(record, opts, first_record_number + i))
+            .collect();
+    }
+
+    let chunk_size = records.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let handles: Vec<_> = records
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(chunk_number, chunk)| {
+                let first_record_number = first_record_number + chunk_number * chunk_size;
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .map(|(i, record)| cleanse_record(record, opts, first_record_number + i))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("cleansing thread panicked"))
+            .collect()
+    })
+}
+
+/// The size in bytes of the buffers used by [`get_input`] and [`get_output`] by default.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Parse a size in bytes, either a plain number or a number followed by one of `B`, `KB`, `MB`,
+/// or `GB`, case insensitively. Each unit is 1024 times the one before it.
+pub fn parse_byte_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => {
+            return Err(format!(
+                "Unknown unit in size {:?}, expected B, KB, MB, or GB",
+                s
+            ))
+        }
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|&size| size > 0)
+        .ok_or_else(|| format!("Invalid size {:?}", s))
+}
+
+/// Open the input path for reading, "-" or `None` reads from stdin.
+///
+/// Input is decompressed with `compression`, or if that isn't given, with the compression matching
+/// the extension of the path. Input in an encoding other than UTF-8 is transcoded to UTF-8, and a
+/// leading BOM is discarded. Input that starts with a UTF-16 BOM is always read as UTF-16.
+///
+/// If `mmap` is set the file is memory mapped rather than read, which requires the path of a
+/// regular file. Otherwise it's read through a buffer of `buffer_size` bytes, or of
+/// [`DEFAULT_BUFFER_SIZE`] if not given.
+pub fn get_input(
+    path: Option<PathBuf>,
+    encoding: InputEncoding,
+    compression: Option<Compression>,
+    mmap: bool,
+    buffer_size: Option<usize>,
+) -> Result<Box<dyn Read>, CleanseError> {
+    let buffer_size = buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    let compression = compression
+        .unwrap_or_else(|| {
+            path.as_ref()
+                .map_or(Compression::None, Compression::from_extension)
+        })
+        .check_supported()?;
+    let reader: Box<dyn BufRead> = match path {
+        Some(path) if path.as_os_str() != "-" => {
+            if mmap {
+                Box::new(io::Cursor::new(Mmap::open(path)?))
+            } else {
+                Box::new(BufReader::with_capacity(buffer_size, File::open(path)?))
+            }
+        }
+        _ if mmap => {
+            return Err(CleanseError::InvalidOptions(String::from(
+                "stdin can't be memory mapped",
+            )))
+        }
+        _ => Box::new(BufReader::with_capacity(buffer_size, io::stdin())),
+    };
+    let mut reader: Box<dyn BufRead> = match compression {
+        Compression::Gzip => Box::new(BufReader::new(GzDecoder::new(reader))),
+        Compression::None | Compression::Zstd | Compression::Bzip2 => reader,
+    };
+    // A UTF-16 BOM is unambiguous, so it takes precedence over the expected encoding
+    let mut encoding = strip_utf16_bom(&mut reader)?.unwrap_or(encoding);
+    if encoding == InputEncoding::Detect {
+        // The sample is put back in front of the rest of the input once it's been looked at
+        let mut sample = vec![];
+        reader
+            .by_ref()
+            .take(DETECT_SAMPLE_LEN as u64)
+            .read_to_end(&mut sample)?;
+        encoding = detect_encoding(&sample);
+        info!("Detected input encoding {:?}", encoding);
+        reader = Box::new(io::Cursor::new(sample).chain(reader));
+    }
+    let reader: Box<dyn Read> = match encoding {
+        InputEncoding::Utf8 => {
+            strip_bom(&mut reader)?;
+            Box::new(reader)
+        }
+        InputEncoding::Latin1 => Box::new(Latin1Reader::new(reader)),
+        InputEncoding::Utf16Le => Box::new(Utf16Reader::new(reader, false)),
+        InputEncoding::Utf16Be => Box::new(Utf16Reader::new(reader, true)),
+        InputEncoding::Detect => unreachable!("The encoding was detected above"),
+    };
+    Ok(reader)
+}
+
+/// The path of chunk number `chunk` of the split output `path`, with the zero padded chunk number
+/// inserted before the extension, i.e. `out.csv` becomes `out_000.csv`.
+pub fn chunk_path(path: &Path, chunk: usize) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Everything from the first `.` is kept as the extension, so `out.csv.gz` keeps `.csv.gz`
+    let (stem, extension) = match name.get(1..).and_then(|rest| rest.find('.')) {
+        Some(i) => name.split_at(i + 1),
+        None => (name.as_str(), ""),
+    };
+    path.with_file_name(format!("{}_{:03}{}", stem, chunk, extension))
+}
+
+/// Open the output path for writing, "-" or `None` writes to stdout.
+///
+/// The output is compressed with `compression`, or if that isn't given, with the compression
+/// matching the extension of the path. `level` is the compression level to use, or the default
+/// for the compression if not given. Output is written through a buffer of `buffer_size` bytes, or
+/// of [`DEFAULT_BUFFER_SIZE`] if not given, and must be finished with [`Output::finish`].
+pub fn get_output(
+    path: Option<PathBuf>,
+    compression: Option<Compression>,
+    level: Option<u8>,
+    buffer_size: Option<usize>,
+) -> Result<Output, CleanseError> {
+    let buffer_size = buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    // Checked before the output is created, so that nothing is written under a compressed name
+    let compression = compression
+        .unwrap_or_else(|| {
+            path.as_ref()
+                .map_or(Compression::None, Compression::from_extension)
+        })
+        .check_supported()?;
+    let writer: Box<dyn Write> = match path {
+        Some(path) if path.as_os_str() != "-" => Box::new(File::create(path)?),
+        _ => Box::new(io::stdout()),
+    };
+    Ok(match compression {
+        Compression::Gzip => Output::Gzip(BufWriter::with_capacity(
+            buffer_size,
+            GzEncoder::new(writer, level.unwrap_or(gzip::DEFAULT_LEVEL)),
+        )),
+        Compression::None => Output::Plain(BufWriter::with_capacity(buffer_size, writer)),
+        Compression::Zstd | Compression::Bzip2 => {
+            unreachable!("Unsupported compressions are rejected above")
+        }
+    })
+}
+
+/// An output opened by [`get_output`].
+///
+/// Dropping it finishes it too, but without any way to report an error, which for a compressed
+/// stream could leave it truncated. Call [`Output::finish`] once everything has been written.
+pub enum Output {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(BufWriter<GzEncoder<Box<dyn Write>>>),
+}
+
+impl Output {
+    /// An output discarding everything written to it.
+    pub fn sink() -> Self {
+        Output::Plain(BufWriter::new(Box::new(io::sink())))
+    }
+
+    /// Flush the output, finishing any compressed stream.
+    pub fn finish(self) -> Result<(), CleanseError> {
+        match self {
+            Output::Plain(mut writer) => writer.flush()?,
+            Output::Gzip(writer) => {
+                writer
+                    .into_inner()
+                    .map_err(|err| err.into_error())?
+                    .finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Discard the first `n` newline terminated lines of `reader`, returning how many were skipped.
+fn skip_lines<R: BufRead>(reader: &mut R, n: usize) -> io::Result<usize> {
+    let mut skipped = 0;
+    while skipped < n {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        match buf.find_byte(b'\n') {
+            Some(i) => {
+                reader.consume(i + 1);
+                skipped += 1;
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+    Ok(skipped)
+}
+
+/// The delimiters considered by [`CleanseOptions::auto_detect`], in order of preference.
+pub const DELIMITER_CANDIDATES: [u8; 5] = [b',', b'\t', b'|', b';', b':'];
+
+/// Pick the candidate delimiter appearing most often in `line`, the first listed in
+/// [`DELIMITER_CANDIDATES`] on a tie, or `None` if no candidate appears at all.
+pub fn detect_delimiter(line: &[u8]) -> Option<u8> {
+    let mut best = None;
+    let mut best_count = 0;
+    for &candidate in DELIMITER_CANDIDATES.iter() {
+        let count = line.iter().filter(|&&byte| byte == candidate).count();
+        if count > best_count {
+            best = Some(candidate);
+            best_count = count;
+        }
+    }
+    best
+}
+
+/// A named delimiter preset, shorthand for setting the delimiter directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelimitedFormat {
+    /// Comma separated values.
+    Csv,
+    /// Tab separated values.
+    Tsv,
+    /// Pipe separated values.
+    Psv,
+    /// Semicolon separated values.
+    Ssv,
+}
+
+impl DelimitedFormat {
+    /// The delimiter used by the format.
+    pub fn delimiter(self) -> u8 {
+        match self {
+            DelimitedFormat::Csv => b',',
+            DelimitedFormat::Tsv => b'\t',
+            DelimitedFormat::Psv => b'|',
+            DelimitedFormat::Ssv => b';',
+        }
+    }
+}
+
+impl FromStr for DelimitedFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(DelimitedFormat::Csv),
+            "tsv" => Ok(DelimitedFormat::Tsv),
+            "psv" => Ok(DelimitedFormat::Psv),
+            "ssv" => Ok(DelimitedFormat::Ssv),
+            _ => Err(format!(
+                "Unknown format {:?}, expected one of csv, tsv, psv, ssv",
+                s
+            )),
+        }
+    }
+}
+
+/// Every header name given to more than one column, with the 0-based columns it's in, in order
+/// of first appearance.
+fn duplicate_headers(headers: &ByteRecord) -> Vec<CleanseError> {
+    headers
+        .iter()
+        .enumerate()
+        .filter(|(column, name)| !headers.iter().take(*column).any(|other| other == *name))
+        .filter_map(|(_, name)| {
+            let columns: Vec<usize> = headers
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other == &name)
+                .map(|(column, _)| column)
+                .collect();
+            if columns.len() > 1 {
+                Some(CleanseError::DuplicateHeader {
+                    name: name.to_str_lossy().into_owned(),
+                    columns,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The number of records [`CleanseOptions::max_change_ratio`] is checked against.
+pub const CHANGE_RATIO_SAMPLE: usize = 1000;
+
+/// The records read so far towards [`CHANGE_RATIO_SAMPLE`], and how many of their fields would
+/// be changed.
+#[derive(Debug, Default)]
+struct ChangeRatioSample {
+    records: usize,
+    fields: usize,
+    fields_changed: usize,
+}
+
+impl ChangeRatioSample {
+    /// Add as many of `records` and their `cleansed` versions as are still needed.
+    fn add(
+        &mut self,
+        cleansed: &[(ByteRecord, Vec<FieldChange>, Vec<FieldViolation>)],
+        records: &[ByteRecord],
+    ) {
+        let sampled = records.len().min(CHANGE_RATIO_SAMPLE - self.records);
+        self.records += sampled;
+        self.fields += records[..sampled]
+            .iter()
+            .map(|record| record.len())
+            .sum::<usize>();
+        self.fields_changed += cleansed[..sampled]
+            .iter()
+            .map(|(_, changes, _)| changes.chunk_by(|a, b| a.field == b.field).count())
+            .sum::<usize>();
+    }
+
+    /// The fraction of the sampled fields that would be changed, 0 without any fields.
+    fn ratio(&self) -> f64 {
+        if self.fields == 0 {
+            0.0
+        } else {
+            self.fields_changed as f64 / self.fields as f64
+        }
+    }
+}
+
+/// Write `header` to `writer` if `to_output`, and to any side outputs that take one.
+fn write_header<W: Write>(
+    writer: &mut RecordWriter<W>,
+    side_outputs: &mut SideOutputs,
+    header: &ByteRecord,
+    to_output: bool,
+    opts: &CleanseOptions,
+) -> Result<(), CleanseError> {
+    if to_output {
+        writer.write_header(header)?;
+    }
+    if let Some(error_output) = side_outputs.error_output.as_mut() {
+        error_output.write_header(header, opts)?;
+    }
+    Ok(())
+}
+
+/// The default for [`CleanseOptions::unique_key_warn_threshold`].
+pub const DEFAULT_UNIQUE_KEY_WARN_THRESHOLD: usize = 10_000_000;
+
+/// The fields of `record` in `columns`, each prefixed with its length so that different fields
+/// can't make the same key.
+fn record_key(record: &ByteRecord, columns: &Columns) -> Vec<u8> {
+    let mut key = vec![];
+    for (_, field) in record
+        .iter()
+        .enumerate()
+        .filter(|(field_number, _)| columns.contains(field_number + 1))
+    {
+        key.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        key.extend_from_slice(field);
+    }
+    key
+}
+
+/// A copy of `headers` with each column named by a rename given its new name.
+fn rename_columns(headers: &ByteRecord, renames: &[ColumnRename]) -> ByteRecord {
+    for rename in renames {
+        if !headers.iter().any(|name| name == rename.from.as_bytes()) {
+            warn!("--rename: no column named {:?} in the header", rename.from);
+        }
+    }
+    headers
+        .iter()
+        .map(|name| {
+            renames
+                .iter()
+                .find(|rename| name == rename.from.as_bytes())
+                .map_or(name, |rename| rename.to.as_bytes())
+        })
+        .collect()
+}
+
+/// Drop the last field of `record` if it's empty, for records that
+/// [`InputReader::ends_with_delimiter`].
+fn strip_trailing_delimiter(record: &mut ByteRecord) {
+    if record.len() > 1 && record.get(record.len() - 1) == Some(b"") {
+        record.truncate(record.len() - 1);
+    }
+}
+
+/// A copy of `record` with `field` added before its first field.
+fn prepend_field(field: &[u8], record: &ByteRecord) -> ByteRecord {
+    let mut prepended =
+        ByteRecord::with_capacity(field.len() + record.as_slice().len(), 1 + record.len());
+    prepended.push_field(field);
+    prepended.extend(record);
+    prepended
+}
+
+/// Optional outputs written to alongside the cleansed records, kept across runs.
+#[derive(Default)]
+pub struct SideOutputs {
+    /// Log of every change made to every field.
+    pub change_log: Option<ChangeLog>,
+    /// Every record with at least one change or violation.
+    pub error_output: Option<ErrorOutput>,
+}
+
+/// Run the program, returning the counts of changes made or any found errors
+pub fn run<R, W>(input: R, output: W, opts: &CleanseOptions) -> Result<RunStats, CleanseError>
+where
+    R: Read,
+    W: Write,
+{
+    run_with_side_outputs(input, output, &mut SideOutputs::default(), opts)
+}
+
+/// Run the program, also writing to any of the `side_outputs`
+pub fn run_with_side_outputs<R, W>(
+    input: R,
+    output: W,
+    side_outputs: &mut SideOutputs,
+    opts: &CleanseOptions,
+) -> Result<RunStats, CleanseError>
+where
+    R: Read,
+    W: Write,
+{
+    let mut output = SharedOutput::new(output);
+    let stats = run_shared(input, &mut output, side_outputs, opts)?;
+    output.finish(opts)?;
+    Ok(stats)
+}
+
+/// Run the program, writing to an `output` shared with other runs, such as one for each of
+/// several inputs. Anything that has to follow the last record is only written once the output
+/// is finished with [`SharedOutput::finish`].
+pub fn run_shared<R, W>(
+    input: R,
+    output: &mut SharedOutput<W>,
+    side_outputs: &mut SideOutputs,
+    opts: &CleanseOptions,
+) -> Result<RunStats, CleanseError>
+where
+    R: Read,
+    W: Write,
+{
+    run_chunks(input, output, None, side_outputs, opts)
+}
+
+/// Run the program, writing every [`CleanseOptions::split_records`] records to a new output
+/// made by `make_output` from its 0-based chunk number. Each output gets its own copy of the
+/// header, and is passed to `finish_output` once everything has been written to it.
+pub fn run_split<R, W, F, G>(
+    input: R,
+    mut make_output: F,
+    mut finish_output: G,
+    side_outputs: &mut SideOutputs,
+    opts: &CleanseOptions,
+) -> Result<RunStats, CleanseError>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(usize) -> Result<W, CleanseError>,
+    G: FnMut(W) -> Result<(), CleanseError>,
+{
+    let mut output = SharedOutput::new(make_output(0)?);
+    let chunks = opts.split_records.map(|split_records| Chunks {
+        split_records,
+        make_output: &mut make_output,
+        finish_output: &mut finish_output,
+    });
+    let stats = run_chunks(input, &mut output, chunks, side_outputs, opts)?;
+    finish_output(output.finish(opts)?)?;
+    Ok(stats)
+}
+
+/// How [`run_split`] starts a new output every `split_records` records.
+struct Chunks<'a, W> {
+    split_records: usize,
+    make_output: &'a mut dyn FnMut(usize) -> Result<W, CleanseError>,
+    finish_output: &'a mut dyn FnMut(W) -> Result<(), CleanseError>,
+}
+
+/// Keeps the bytes read through it until the CSV reader is done with them, so that a record
+/// ending in a delimiter can be told apart from one ending in a quoted empty field.
+struct RawInput<R> {
+    inner: R,
+    keep: bool,
+    /// The input position of the first byte in `raw`.
+    start: u64,
+    raw: Vec<u8>,
+    terminators: &'static [u8],
+}
+
+impl<R: Read> RawInput<R> {
+    fn new(inner: R, keep: bool, terminators: &'static [u8]) -> Self {
+        Self {
+            inner,
+            keep,
+            start: 0,
+            raw: vec![],
+            terminators,
+        }
+    }
+
+    /// Whether the record ending at input position `end` ended with `delimiter` before any
+    /// terminator, forgetting the input before `end`.
+    fn ends_with_delimiter(&mut self, end: u64, delimiter: u8) -> bool {
+        let len = (end.saturating_sub(self.start) as usize).min(self.raw.len());
+        let ends_with_delimiter = self.raw[..len]
+            .iter()
+            .rev()
+            .find(|b| !self.terminators.contains(b))
+            == Some(&delimiter);
+        self.raw.drain(..len);
+        self.start += len as u64;
+        ends_with_delimiter
+    }
+}
+
+impl<R: Read> Read for RawInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if self.keep {
+            self.raw.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// Reads records from either delimited or, with `--fixed-width`, fixed width input.
+enum InputReader<R> {
+    Csv(csv::Reader<RawInput<R>>),
+    FixedWidth(FixedWidthReader<R>),
+}
+
+impl<R: BufRead> InputReader<R> {
+    fn new(input: R, opts: &CleanseOptions) -> Self {
+        let (terminator, csv_terminator, terminators) = if opts.nul_terminated {
+            (b'\0', csv::Terminator::Any(b'\0'), &b"\0"[..])
+        } else {
+            (b'\n', csv::Terminator::CRLF, &b"\r\n"[..])
+        };
+        match &opts.fixed_width {
+            Some(widths) => {
+                InputReader::FixedWidth(FixedWidthReader::new(input, widths, terminator))
+            }
+            None => InputReader::Csv(
+                csv::ReaderBuilder::new()
+                    .has_headers(opts.has_headers)
+                    .delimiter(opts.delimiter)
+                    .quote(opts.quote)
+                    // Only some records may end with a delimiter when stripping them
+                    .flexible(opts.checks_field_count() || opts.strip_trailing_delimiter)
+                    .terminator(csv_terminator)
+                    .from_reader(RawInput::new(
+                        input,
+                        opts.strip_trailing_delimiter,
+                        terminators,
+                    )),
+            ),
+        }
+    }
+
+    /// Read the header, which for fixed width input is just the first line.
+    fn byte_headers(&mut self) -> Result<ByteRecord, csv::Error> {
+        match self {
+            InputReader::Csv(reader) => Ok(reader.byte_headers()?.clone()),
+            InputReader::FixedWidth(reader) => {
+                let mut headers = ByteRecord::new();
+                reader.read_byte_record(&mut headers)?;
+                Ok(headers)
+            }
+        }
+    }
+
+    fn read_byte_record(&mut self, record: &mut ByteRecord) -> Result<bool, csv::Error> {
+        match self {
+            InputReader::Csv(reader) => reader.read_byte_record(record),
+            InputReader::FixedWidth(reader) => Ok(reader.read_byte_record(record)?),
+        }
+    }
+
+    /// Whether the record just read ended with a delimiter, which fixed width input never does.
+    /// Only known when [`CleanseOptions::strip_trailing_delimiter`] is set.
+    fn ends_with_delimiter(&mut self, delimiter: u8) -> bool {
+        match self {
+            InputReader::Csv(reader) => {
+                let end = reader.position().byte();
+                reader.get_mut().ends_with_delimiter(end, delimiter)
+            }
+            InputReader::FixedWidth(_) => false,
+        }
+    }
+
+    /// The number of bytes read from the input so far, for the progress bar.
+    fn bytes_read(&self) -> u64 {
+        match self {
+            InputReader::Csv(reader) => reader.position().byte(),
+            InputReader::FixedWidth(reader) => reader.bytes_read(),
+        }
+    }
+}
+
+fn run_chunks<R, W>(
+    input: R,
+    output: &mut SharedOutput<W>,
+    mut chunks: Option<Chunks<W>>,
+    side_outputs: &mut SideOutputs,
+    opts: &CleanseOptions,
+) -> Result<RunStats, CleanseError>
+where
+    R: Read,
+    W: Write,
+{
+    let start = Instant::now();
+    let mut input = BufReader::new(Follow::new(input, opts.watch_interval));
+    skip_lines(&mut input, opts.skip_lines)?;
+
+    // The sniffed line is put back in front of the rest of the input before parsing
+    let mut first_line = vec![];
+    let detected_opts;
+    let opts = if opts.auto_detect {
+        input.read_until(
+            if opts.nul_terminated { b'\0' } else { b'\n' },
+            &mut first_line,
+        )?;
+        match detect_delimiter(&first_line) {
+            Some(delimiter) => {
+                info!("Detected delimiter {:?}", delimiter as char);
+                let mut detected = opts.clone();
+                if opts.output_delimiter == opts.delimiter {
+                    detected.output_delimiter = delimiter;
+                }
+                detected.delimiter = delimiter;
+                detected_opts = detected;
+                &detected_opts
+            }
+            None => {
+                warn!(
+                    "Couldn't detect the delimiter, using {:?}",
+                    opts.delimiter as char
+                );
+                opts
+            }
+        }
+    } else {
+        opts
+    };
+    let input = io::Cursor::new(first_line).chain(input);
+    let mut reader = InputReader::new(input, opts);
+
+    let mut chunk = 0;
+    let mut chunk_records = 0;
+
+    let mut stats = RunStats::default();
+    let mut header = None;
+    let mut header_to_output = false;
+    if opts.has_headers {
+        let mut headers = reader.byte_headers()?;
+        if opts.strip_trailing_delimiter && reader.ends_with_delimiter(opts.delimiter) {
+            strip_trailing_delimiter(&mut headers);
+        }
+        for duplicate in duplicate_headers(&headers) {
+            if opts.strict_fields {
+                return Err(duplicate);
+            }
+            warn!("{}", duplicate);
+        }
+        if !headers.is_empty() {
+            let mut kept = ByteRecord::with_capacity(headers.as_slice().len(), headers.len() + 1);
+            for (field_number, field) in headers.iter().enumerate() {
+                match &opts.split_field {
+                    _ if is_dropped(opts, field_number) => {}
+                    Some(split_field) if split_field.column == field_number + 1 => {
+                        kept.push_field(split_field.first_name.as_bytes());
+                        kept.push_field(split_field.second_name.as_bytes());
+                    }
+                    _ => kept.push_field(field),
+                }
+            }
+            let mut headers = kept;
+            if let Some(reorder_columns) = &opts.reorder_columns {
+                if headers.len() != reorder_columns.order.len() {
+                    return Err(CleanseError::InvalidOptions(format!(
+                        "--reorder-columns lists {} columns, but the header has {}",
+                        reorder_columns.order.len(),
+                        headers.len()
+                    )));
+                }
+                headers = reorder_columns.apply(&headers);
+            }
+            if !opts.renames.is_empty() {
+                headers = rename_columns(&headers, &opts.renames);
+            }
+            if let Some(case) = opts.header_case {
+                headers = headers.iter().map(|name| case.apply(name)).collect();
+            }
+            if opts.add_record_number {
+                headers = prepend_field(opts.record_number_header.as_bytes(), &headers);
+            }
+            if opts.source_filename.is_some() {
+                headers.push_field(b"_source_file");
+            }
+            if opts.statistics {
+                stats.name_columns(&headers);
+            }
+            // Keyed formats still need the header for their keys
+            let skip_header = opts.skip_header && opts.output_format == OutputFormat::Csv;
+            header_to_output = !(opts.dry_run || skip_header);
+            header = Some(headers);
+        }
+    }
+    // Held back until the change ratio is checked, so that nothing is written if it's exceeded
+    let mut header_pending = header.is_some() && opts.max_change_ratio.is_some();
+    if let (Some(header), false) = (&header, header_pending) {
+        write_header(
+            output.writer(opts),
+            side_outputs,
+            header,
+            header_to_output,
+            opts,
+        )?;
+    }
+
+    let mut is_more = true;
+    let mut skipped_record = ByteRecord::new();
+    while is_more && stats.records_skipped < opts.skip_records as u64 {
+        match reader.read_byte_record(&mut skipped_record) {
+            Ok(more) => is_more = more,
+            // The record is being discarded anyway, so it may as well be malformed
+            Err(err) if opts.skip_malformed && !err.is_io_error() => {}
+            Err(err) => return Err(err.into()),
+        }
+        if is_more {
+            stats.records_skipped += 1;
+        }
+    }
+
+    // Skipped records still take up a record number
+    let mut record_number = opts.first_record_number + stats.records_skipped as usize;
+    // A batch would wait for more input to fill it when watching
+    let batch_size = if opts.watch_interval.is_some() {
+        1
+    } else if opts.max_change_ratio.is_some() {
+        // So that the change ratio sample usually fits in the first batch
+        opts.batch_size.max(CHANGE_RATIO_SAMPLE)
+    } else {
+        opts.batch_size.max(1)
+    };
+    // The column options, and whether a record too short for them has been warned about yet
+    let mut column_warnings = [
+        ("--columns", opts.columns.as_ref(), false),
+        ("--drop-columns", opts.drop_columns.as_ref(), false),
+    ];
+    let mut change_ratio_checked = opts.max_change_ratio.is_none();
+    let mut sample = ChangeRatioSample::default();
+    // Batches read while the sample is being collected, with their first record numbers, as no
+    // record is written until the change ratio is checked
+    let mut held_batches = vec![];
+    let mut batch = vec![ByteRecord::new(); batch_size];
+    // The last record read, for --dedup-adjacent, which is `None` until the first one is read
+    let mut previous: Option<ByteRecord> = None;
+    let mut keys = HashSet::new();
+    let mut progress = if opts.progress {
+        Some(Progress::new(opts.input_size))
+    } else {
+        None
+    };
+
+    while is_more {
+        let mut batch_len = 0;
+        // Records skipped at the end of this batch, they still take up a record number
+        let mut skipped = 0;
+        let batch_limit = match opts.max_records {
+            Some(max_records) => max_records
+                .saturating_sub(stats.records as usize)
+                .min(batch.len()),
+            None => batch.len(),
+        };
+        while batch_len < batch_limit {
+            match reader.read_byte_record(&mut batch[batch_len]) {
+                Ok(true) => {}
+                Ok(false) => {
+                    is_more = false;
+                    break;
+                }
+                Err(err) if opts.raw_split_malformed && !err.is_io_error() => {
+                    let raw = bstr::join([opts.delimiter], batch[batch_len].iter());
+                    warn!(
+                        "Record number {}: splitting malformed record {:?} on the delimiter: {}",
+                        record_number + batch_len,
+                        raw.as_bstr(),
+                        err
+                    );
+                    batch[batch_len] = raw.split(|&b| b == opts.delimiter).collect();
+                    stats.raw_split_records += 1;
+                }
+                Err(err) if opts.skip_malformed && !err.is_io_error() => {
+                    let raw = bstr::join([opts.delimiter], batch[batch_len].iter());
+                    warn!(
+                        "Record number {}: skipping malformed record {:?}: {}",
+                        record_number + batch_len,
+                        raw.as_bstr(),
+                        err
+                    );
+                    stats.malformed_records += 1;
+                    // End the batch early so that the records in it keep consecutive numbers
+                    skipped = 1;
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            }
+            if opts.strip_trailing_delimiter && reader.ends_with_delimiter(opts.delimiter) {
+                strip_trailing_delimiter(&mut batch[batch_len]);
+            }
+            if let Some(expected) = opts.expected_fields {
+                let found = batch[batch_len].len();
+                if found != expected {
+                    let err = CleanseError::FieldCountMismatch {
+                        record: record_number + batch_len,
+                        expected,
+                        found,
+                    };
+                    if opts.strict_fields {
+                        return Err(err);
+                    }
+                    warn!("{}", err);
+                    stats.field_count_mismatches += 1;
+                }
+            }
+            let found = batch[batch_len].len();
+            let out_of_range = match (opts.min_fields, opts.max_fields) {
+                (Some(min), _) if found < min => {
+                    stats.field_count_under += 1;
+                    Some(min)
+                }
+                (_, Some(max)) if found > max => {
+                    stats.field_count_over += 1;
+                    Some(max)
+                }
+                _ => None,
+            };
+            if let Some(limit) = out_of_range {
+                let err = CleanseError::FieldCountOutOfRange {
+                    record: record_number + batch_len,
+                    limit,
+                    found,
+                };
+                if opts.strict_fields {
+                    return Err(err);
+                }
+                warn!("{}", err);
+            }
+            for (option, columns, warned) in column_warnings.iter_mut() {
+                let max_column = columns.and_then(|columns| columns.min_fields());
+                if let (Some(max_column), false) = (max_column, *warned) {
+                    if max_column > found {
+                        warn!(
+                            "Record number {}: column {} of {} is out of range for a record with \
+                             {} fields, later records aren't warned about",
+                            record_number + batch_len,
+                            max_column,
+                            option,
+                            found
+                        );
+                        *warned = true;
+                    }
+                }
+            }
+            // Checked as records are read, so the first invalid field is the one reported
+            if opts.no_encoding_fix {
+                if let Some(field) = batch[batch_len].iter().position(|f| f.to_str().is_err()) {
+                    return Err(CleanseError::EncodingError {
+                        record: record_number + batch_len,
+                        field,
+                    });
+                }
+            }
+            batch_len += 1;
+        }
+
+        let cleansed = cleanse_batch(&batch[..batch_len], opts, record_number);
+        let batch_record_number = record_number;
+        record_number += batch_len + skipped;
+        stats.records += batch_len as u64;
+        stats.fields += batch[..batch_len]
+            .iter()
+            .map(|record| record.len() as u64)
+            .sum::<u64>();
+        // Skipped malformed records end a batch early, so it may take more than one to get there
+        if let Some(max_records) = opts.max_records {
+            if stats.records as usize >= max_records {
+                is_more = false;
+            }
+        }
+
+        let batches = if change_ratio_checked {
+            vec![(
+                batch_record_number,
+                Cow::Borrowed(&batch[..batch_len]),
+                cleansed,
+            )]
+        } else {
+            sample.add(&cleansed, &batch[..batch_len]);
+            held_batches.push((
+                batch_record_number,
+                Cow::Owned(batch[..batch_len].to_vec()),
+                cleansed,
+            ));
+            if sample.records < CHANGE_RATIO_SAMPLE && is_more {
+                continue;
+            }
+            let max_ratio = opts.max_change_ratio.expect("only sampled with a maximum");
+            let ratio = sample.ratio();
+            if ratio > max_ratio {
+                return Err(CleanseError::ChangeRatioExceeded {
+                    records: sample.records,
+                    ratio,
+                    max_ratio,
+                });
+            }
+            change_ratio_checked = true;
+            mem::take(&mut held_batches)
+        };
+        if let (Some(header), true) = (&header, header_pending) {
+            write_header(
+                output.writer(opts),
+                side_outputs,
+                header,
+                header_to_output,
+                opts,
+            )?;
+            header_pending = false;
+        }
+        for (record_number, batch, cleansed) in batches {
+            for (i, (writer_record, changes, violations)) in cleansed.into_iter().enumerate() {
+                let adjacent_duplicate = opts.dedup_adjacent
+                    && match previous.as_mut() {
+                        Some(previous) if *previous == batch[i] => true,
+                        Some(previous) => {
+                            previous.clone_from(&batch[i]);
+                            false
+                        }
+                        None => {
+                            previous = Some(batch[i].clone());
+                            false
+                        }
+                    };
+                let mut duplicate_key = false;
+                if let (Some(unique_key), false) = (&opts.unique_key, adjacent_duplicate) {
+                    if !keys.insert(record_key(&batch[i], unique_key)) {
+                        warn!(
+                            "Record number {}: duplicate key {:?}",
+                            record_number + i,
+                            bstr::join(
+                                [opts.delimiter],
+                                batch[i]
+                                    .iter()
+                                    .enumerate()
+                                    .filter(
+                                        |(field_number, _)| unique_key.contains(field_number + 1)
+                                    )
+                                    .map(|(_, field)| field)
+                            )
+                            .as_bstr()
+                        );
+                        stats.duplicate_keys += 1;
+                        duplicate_key = true;
+                    } else if keys.len() == opts.unique_key_warn_threshold + 1 {
+                        warn!(
+                            "More than {} distinct keys are being kept in memory for --unique-key",
+                            opts.unique_key_warn_threshold
+                        );
+                    }
+                }
+                let change_count = changes.len();
+                let too_many_changes = opts
+                    .max_errors_per_record
+                    .filter(|max_errors| change_count > *max_errors);
+                let empty =
+                    opts.remove_empty_records && writer_record.iter().all(|field| field.is_empty());
+                let dropped = adjacent_duplicate
+                    || (duplicate_key && opts.drop_duplicates)
+                    || too_many_changes.is_some()
+                    || empty;
+                let writer_record = match &opts.reorder_columns {
+                    Some(reorder_columns) if writer_record.len() == reorder_columns.order.len() => {
+                        reorder_columns.apply(&writer_record)
+                    }
+                    // A dropped record is still written to the error output, just as it is
+                    Some(reorder_columns) if !dropped => {
+                        return Err(CleanseError::FieldCountMismatch {
+                            record: record_number + i,
+                            expected: reorder_columns.order.len(),
+                            found: writer_record.len(),
+                        })
+                    }
+                    _ => writer_record,
+                };
+                let mut writer_record = if opts.add_record_number {
+                    let number = (record_number + i + 1).to_string();
+                    prepend_field(number.as_bytes(), &writer_record)
+                } else {
+                    writer_record
+                };
+                if let Some(source_filename) = &opts.source_filename {
+                    writer_record.push_field(source_filename.as_bytes());
+                }
+                // Written before any record is dropped, so that dropped records can be reviewed too
+                if let Some(error_output) = side_outputs.error_output.as_mut() {
+                    if change_count > 0 || !violations.is_empty() {
+                        error_output.write_record(&writer_record, opts)?;
+                        stats.records_written_to_error_output += 1;
+                    }
+                }
+                if adjacent_duplicate {
+                    stats.adjacent_duplicates_dropped += 1;
+                    continue;
+                }
+                if duplicate_key && opts.drop_duplicates {
+                    continue;
+                }
+                if change_count > 0 {
+                    stats.records_changed += 1;
+                }
+                for field_changes in changes.chunk_by(|a, b| a.field == b.field) {
+                    stats.fields_changed += 1;
+                    let kinds: Vec<String> = field_changes
+                        .iter()
+                        .map(|change| change.kind.to_string())
+                        .collect();
+                    info!(
+                        "Record number {}, field number {}: {}",
+                        field_changes[0].record,
+                        field_changes[0].field,
+                        kinds.join(", ")
+                    );
+                    // Every change to a field has the same original and cleaned field, so print one
+                    if opts.diff {
+                        eprintln!("{}", field_changes[0].diff());
+                    }
+                    field_changes
+                        .iter()
+                        .for_each(|change| stats.add(change.kind));
+                    if let Some(change_log) = side_outputs.change_log.as_mut() {
+                        change_log.log(field_changes)?;
+                    }
+                }
+                for violation in &violations {
+                    if opts.strict_fields && violation.kind == Violation::RequiredFieldEmpty {
+                        return Err(CleanseError::RequiredFieldEmpty {
+                            record: violation.record,
+                            field: violation.field,
+                        });
+                    }
+                    // Fields with violations are left as is, so need more attention than changes
+                    warn!(
+                        "Record number {}, field number {}: {}",
+                        violation.record, violation.field, violation.kind
+                    );
+                    stats.add_violation(violation.kind);
+                }
+                if opts.collect_changes {
+                    stats.changes.extend(changes);
+                    stats.violations.extend(violations);
+                }
+                if let Some(max_errors) = too_many_changes {
+                    warn!(
+                    "Record number {}: dropping record with {} changes, more than the {} allowed \
+                     by --max-errors-per-record",
+                    record_number + i,
+                    change_count,
+                    max_errors
+                );
+                    stats.records_dropped += 1;
+                    continue;
+                }
+                if empty {
+                    stats.empty_records_dropped += 1;
+                    continue;
+                }
+                if opts.statistics {
+                    stats.count_columns(&writer_record);
+                }
+                if !opts.dry_run {
+                    if let Some(chunks) = chunks.as_mut() {
+                        // Only started once there's a record for it, so no output is left empty
+                        if chunk_records == chunks.split_records.max(1) {
+                            chunk += 1;
+                            chunk_records = 0;
+                            let next = SharedOutput::new((chunks.make_output)(chunk)?);
+                            (chunks.finish_output)(mem::replace(output, next).finish(opts)?)?;
+                            if let Some(header) = &header {
+                                output.writer(opts).write_header(header)?;
+                            }
+                        }
+                        chunk_records += 1;
+                    }
+                    let writer = output.writer(opts);
+                    writer.write_record(&writer_record)?;
+                    if opts.watch_interval.is_some() {
+                        writer.flush()?;
+                    }
+                }
+            }
+        }
+        if let Some(progress) = progress.as_mut() {
+            progress.update(stats.records, reader.bytes_read());
+        }
+    }
+    if let Some(progress) = progress.as_mut() {
+        progress.finish(stats.records, reader.bytes_read());
+    }
+    // There were no records to check the change ratio against
+    if let (Some(header), true) = (&header, header_pending) {
+        write_header(
+            output.writer(opts),
+            side_outputs,
+            header,
+            header_to_output,
+            opts,
+        )?;
+    }
+    output.writer(opts).flush()?;
+    if let Some(change_log) = side_outputs.change_log.as_mut() {
+        change_log.flush()?;
+    }
+    if let Some(error_output) = side_outputs.error_output.as_mut() {
+        error_output.flush()?;
+    }
+    stats.elapsed = start.elapsed();
+
+    let summary: Vec<String> = stats
+        .change_counts()
+        .iter()
+        .map(|(change, count)| format!("{}: {}", change, count))
+        .collect();
+    info!("{}", summary.join(", "));
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(changes: &[FieldChange]) -> Vec<CleanseChanges> {
+        changes.iter().map(|change| change.kind).collect()
+    }
+
+    fn csv_opts() -> CleanseOptions {
+        CleanseOptions::default()
+            .delimiter(b',')
+            .output_delimiter(b',')
+    }
+
+    #[test]
+    fn test_simple() {
+        let input = b"\
+        a,b,c,d\n\
+        1,\"2,3\",4,5\n\
+        this,is,\"a\n\
+        very gross\",li\xffe\n"
+            .to_vec();
+
+        let expected = String::from(
+            "\
+        a,b,c,d\n\
+        1,2 3,4,5\n\
+        this,is,a very gross,li�e\n",
+        );
+
+        let mut writer = vec![];
+        let stats = run(input.as_slice(), &mut writer, &csv_opts()).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(
+            stats,
+            RunStats {
+                records: 3,
+                fields: 12,
+                records_changed: 2,
+                fields_changed: 3,
+                delimiter_replacements: 1,
+                terminator_replacements: 1,
+                carriage_return_replacements: 0,
+                encoding_fixes: 1,
+                elapsed: stats.elapsed,
+                ..RunStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_replacement() {
+        let input = b"\
+        1,\"2,3\",4\n\
+        this,\"a\n\
+        very gross\",field\n"
+            .to_vec();
+
+        let expected = String::from(
+            "\
+        1,23,4\n\
+        this,avery gross,field\n",
+        );
+
+        let mut writer = vec![];
+        let opts = csv_opts().replacement(String::new());
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_multi_byte_replacement() {
+        let input = b"1,\"2,3\",4\nthis,\"a\nb\0c\",field\n".to_vec();
+
+        for replacement in ["[X]", "«·»"] {
+            let expected = format!("1,2{r}3,4\nthis,a{r}b{r}c,field\n", r = replacement);
+            let mut writer = vec![];
+            let opts = csv_opts().replacement(replacement.to_string());
+            let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+            assert_eq!(expected, writer.into_string().unwrap());
+            assert_eq!(stats.delimiter_replacements, 1);
+            assert_eq!(stats.terminator_replacements, 1);
+            assert_eq!(stats.null_byte_replacements, 1);
+            assert_eq!(stats.total_changes(), 3);
+        }
+    }
+
+    #[test]
+    fn test_separate_replacements() {
+        let input = b"\"a,b\",\"c\nd\",\"e\r\nf\0g\"\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .delimiter_replacement(Some(String::from(";")))
+            .terminator_replacement(Some(String::from("\\n")));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a;b,c\\nd,e\\nf g\n");
+        assert_eq!(stats.delimiter_replacements, 1);
+        assert_eq!(stats.terminator_replacements, 1);
+        assert_eq!(stats.carriage_return_replacements, 1);
+        assert_eq!(stats.null_byte_replacements, 1);
+
+        // Each falls back to the shared replacement
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .replacement(String::from("_"))
+            .terminator_replacement(Some(String::from("|")));
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a_b,c|d,e|f_g\n");
+
+        // Both in the same field
+        let opts = csv_opts()
+            .delimiter_replacement(Some(String::from("|")))
+            .terminator_replacement(Some(String::from(" ")));
+        let (cleaned, changes) = cleanse_field(b"a,b\nc,d", &opts, 0, 0);
+        assert_eq!(cleaned, "a|b c|d");
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                CleanseChanges::DelimiterReplacement,
+                CleanseChanges::TerminatorReplacement
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nul_terminated() {
+        let input = b"a,\"b\nc\"\0d,\"e,\r\nf\"\0".to_vec();
+        // Newlines don't need quoting when they aren't the terminator
+        let expected = String::from("a,b\nc\0d,e \r\nf\0");
+
+        let mut writer = vec![];
+        let opts = csv_opts().nul_terminated(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.delimiter_replacements, 1);
+        assert_eq!(stats.terminator_replacements, 0);
+        assert_eq!(stats.carriage_return_replacements, 0);
+    }
+
+    #[test]
+    fn test_non_ascii_delimiter() {
+        // A Latin-1 delimiter byte must be matched as a byte, not as its UTF-8 encoding
+        let opts = csv_opts().delimiter(0xA7);
+        let (cleaned, changes) = cleanse_field(b"a\xA7b", &opts, 0, 0);
+        assert_eq!(cleaned, "a b");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::DelimiterReplacement]);
+    }
+
+    #[test]
+    fn test_carriage_return() {
+        let input = b"a,\"b\rc\",\"d\r\ne\"\n".to_vec();
+        let expected = String::from("a,b c,d e\n");
+
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &csv_opts()).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let opts = csv_opts().replacement(String::from("_"));
+        let (field, changes) = cleanse_field(b"a\r\nb", &opts, 0, 0);
+        assert_eq!(field, "a_b");
+        assert_eq!(
+            kinds(&changes),
+            vec![CleanseChanges::CarriageReturnReplacement]
+        );
+
+        let (field, changes) = cleanse_field(b"a\rb", &opts, 0, 0);
+        assert_eq!(field, "a_b");
+        assert_eq!(
+            kinds(&changes),
+            vec![CleanseChanges::CarriageReturnReplacement]
+        );
+
+        let (field, changes) = cleanse_field(b"a\nb", &opts, 0, 0);
+        assert_eq!(field, "a_b");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::TerminatorReplacement]);
+
+        let (field, changes) = cleanse_field(b"a\r\nb\rc\nd\n\re", &opts, 0, 0);
+        assert_eq!(field, "a_b_c_d__e");
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                CleanseChanges::CarriageReturnReplacement,
+                CleanseChanges::TerminatorReplacement
+            ]
+        );
+    }
+
+    #[test]
+    fn test_output_delimiter() {
+        let input = b"a\tb,c\td\n1\t\"2\t3\"\t4\n".to_vec();
+        let expected = String::from("a,\"b,c\",d\n1,2 3,4\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().delimiter(b'\t');
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let input = b"a,b\n\"c,d\",e\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().dry_run(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert!(writer.is_empty());
+        assert_eq!(stats.total_changes(), 1);
+    }
+
+    #[test]
+    fn test_count() {
+        let input = b"x,y\na,b\nc,d\n\"e,f\",g\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().dry_run(true).has_headers(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert!(writer.is_empty());
+        assert_eq!(stats.records, 3);
+    }
+
+    #[test]
+    fn test_summary() {
+        let input = b"\"a,b\",c\n\"d\ne,f\",g\nh,i\n".to_vec();
+
+        let mut writer = vec![];
+        let stats = run(input.as_slice(), &mut writer, &csv_opts()).unwrap();
+        let mut summary = stats.summary();
+        assert!(summary["elapsed_seconds"].as_f64().unwrap() >= 0.0);
+        summary["elapsed_seconds"] = serde_json::json!(0.0);
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "total_records": 3,
+                "total_fields": 6,
+                "records_changed": 2,
+                "fields_changed": 2,
+                "delimiter_replacements": 2,
+                "terminator_replacements": 1,
+                "encoding_fixes": 0,
+                "elapsed_seconds": 0.0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_has_headers() {
+        let input = b"\"a,b\",c\n\"1,2\",3\n".to_vec();
+        let expected = String::from("\"a,b\",c\n1 2,3\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().has_headers(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.delimiter_replacements, 1);
+    }
+
+    #[test]
+    fn test_batches_preserve_order() {
+        let input: Vec<u8> = (0..100)
+            .flat_map(|i| format!("{},\"{},{}\"\n", i, i, i).into_bytes())
+            .collect();
+        let expected: String = (0..100).map(|i| format!("{},{} {}\n", i, i, i)).collect();
+
+        let mut writer = vec![];
+        let opts = csv_opts().batch_size(7).threads(3);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.delimiter_replacements, 100);
+    }
+
+    #[test]
+    fn test_multiple_inputs() {
+        let first = b"a,\"b,c\"\n".to_vec();
+        let second = b"d,\"e,f\"\n".to_vec();
+        let expected = String::from("a,b c\nd,e f\n");
+
+        let mut writer = vec![];
+        let mut stats = RunStats::default();
+        for input in [first, second].iter() {
+            let opts = csv_opts().first_record_number(stats.records as usize);
+            stats.merge(&run(input.as_slice(), &mut writer, &opts).unwrap());
+        }
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.delimiter_replacements, 2);
+    }
+
+    #[test]
+    fn test_source_filename() {
+        let mut writer = vec![];
+        for (input, name) in [(&b"h\na\n"[..], "first.csv"), (b"h\nb\n", "second.csv")] {
+            let opts = csv_opts()
+                .has_headers(true)
+                .source_filename(Some(name.to_string()));
+            run(input, &mut writer, &opts).unwrap();
+        }
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "h,_source_file\na,first.csv\nh,_source_file\nb,second.csv\n"
+        );
+    }
+
+    #[test]
+    fn test_fixed_width() {
+        let input = b"Smith     John, Jr.           NY\nDoe       Jane\n".to_vec();
+        let mut writer = vec![];
+        let opts = csv_opts().fixed_width(Some("10,20,2".parse().unwrap()));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "Smith,John  Jr.,NY\nDoe,Jane,\n"
+        );
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.delimiter_replacements, 1);
+    }
+
+    #[test]
+    fn test_skip_header() {
+        let mut writer = vec![];
+        for (i, input) in [&b"h1,h2\na,b\n"[..], b"h1,h2\nc,d\n"].iter().enumerate() {
+            let opts = csv_opts().has_headers(true).skip_header(i > 0);
+            run(*input, &mut writer, &opts).unwrap();
+        }
+        assert_eq!(writer.into_string().unwrap(), "h1,h2\na,b\nc,d\n");
+
+        // Keyed formats still need the header
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .has_headers(true)
+            .skip_header(true)
+            .output_format(OutputFormat::Ndjson);
+        run(&b"h1\na\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "{\"h1\":\"a\"}\n");
+    }
+
+    #[test]
+    fn test_latin1_input() {
+        let input = b"caf\xe9,\"a,b\"\n".to_vec();
+        let expected = b"caf\xc3\xa9,a b\n".to_vec();
+
+        let mut writer = vec![];
+        let stats = run(
+            Latin1Reader::new(input.as_slice()),
+            &mut writer,
+            &csv_opts(),
+        )
+        .unwrap();
+        assert_eq!(expected, writer);
+        assert_eq!(stats.encoding_fixes, 0);
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let input = b"(555) 123-4567,<b>bold</b>\n".to_vec();
+        let expected = String::from("5551234567,bold\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().regex_replacements(vec![
+            "[()\\- ]=".parse().unwrap(),
+            "<(/?)b>=".parse().unwrap(),
+        ]);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.regex_replacements, 2);
+    }
+
+    #[test]
+    fn test_regex_replace_capture_groups() {
+        let opts = csv_opts()
+            .regex_replacements(vec![r"(\d{4})-(\d{2})-(\d{2})=$3/$2/$1".parse().unwrap()]);
+        let (field, changes) = cleanse_field(b"2021-08-18", &opts, 0, 0);
+        assert_eq!(field, "18/08/2021");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::RegexReplacement]);
+    }
+
+    #[test]
+    fn test_quote_style() {
+        let input = b"a,1,\"b c\"\n".to_vec();
+
+        for (style, expected) in [
+            ("always", "\"a\",\"1\",\"b c\"\n"),
+            ("necessary", "a,1,b c\n"),
+            ("non-numeric", "\"a\",1,\"b c\"\n"),
+            ("never", "a,1,b c\n"),
+        ]
+        .iter()
+        {
+            let mut writer = vec![];
+            let opts = csv_opts().quote_style(parse_quote_style(style).unwrap());
+            run(input.as_slice(), &mut writer, &opts).unwrap();
+            assert_eq!(*expected, writer.into_string().unwrap());
+        }
+        assert!(parse_quote_style("sometimes").is_err());
+    }
+
+    #[test]
+    fn test_force_quote() {
+        let input = b"h1,h2,h3\na,1,\"b,c\"\n\"say \"\"hi\"\"\",,\"\"\"\"\n".to_vec();
+        let expected = String::from(
+            "\"h1\",\"h2\",\"h3\"\n\"a\",\"1\",\"b c\"\n\"say \"\"hi\"\"\",\"\",\"\"\"\"\n",
+        );
+
+        let mut writer = vec![];
+        let opts = csv_opts().quote_style(QuoteStyle::Always).has_headers(true);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let input = b"col1,col2\nval1,val2\n\"a\nb\",\"c\"\"d\"\n".to_vec();
+        let expected = String::from(
+            "{\"col1\":\"val1\",\"col2\":\"val2\"}\n{\"col1\":\"a b\",\"col2\":\"c\\\"d\"}\n",
+        );
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .has_headers(true)
+            .output_format(OutputFormat::Ndjson);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_json_array() {
+        let input = b"col1,col2\nval1,val2\n\"a\nb\",\"c\"\"d\"\n".to_vec();
+        let expected = String::from(
+            "[\n{\"col1\":\"val1\",\"col2\":\"val2\"},\n\
+             {\"col1\":\"a b\",\"col2\":\"c\\\"d\"}\n]\n",
+        );
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .has_headers(true)
+            .output_format(OutputFormat::JsonArray);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        let output = writer.into_string().unwrap();
+        assert_eq!(expected, output);
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(value[1]["col2"], "c\"d");
+
+        let mut writer = vec![];
+        run(&b"col1,col2\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn test_shared_json_array() {
+        let opts = csv_opts()
+            .has_headers(true)
+            .output_format(OutputFormat::JsonArray);
+        let mut output = SharedOutput::new(vec![]);
+        let mut side_outputs = SideOutputs::default();
+        for input in [&b"col1,col2\na,b\n"[..], &b"col1,col2\nc,d\ne,f\n"[..]].iter() {
+            run_shared(*input, &mut output, &mut side_outputs, &opts).unwrap();
+        }
+        let output = output.finish(&opts).unwrap().into_string().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(value.as_array().unwrap().len(), 3);
+        assert_eq!(value[2]["col2"], "f");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let input = b"name,notes\nalice,\"a|b\"\nbob,\n".to_vec();
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .has_headers(true)
+            .output_format(OutputFormat::Markdown);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "\
+| name  | notes |
+| ----- | ----- |
+| alice | a\\|b  |
+| bob   |       |
+"
+        );
+
+        let mut writer = vec![];
+        let opts = csv_opts().output_format(OutputFormat::Markdown);
+        run(&b"a,bb\nccc,d\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "| 0   | 1   |\n| --- | --- |\n| a   | bb  |\n| ccc | d   |\n"
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let input = b"name,notes\nalice,caf\xe9\nbob,\"a\nb\"\nc,\n".to_vec();
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .has_headers(true)
+            .output_format(OutputFormat::Table);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        let output = writer.into_string().unwrap();
+        assert_eq!(
+            output,
+            "\
+┌───────┬───────┐
+│ name  │ notes │
+├───────┼───────┤
+│ alice │ caf\u{FFFD}  │
+│ bob   │ a b   │
+│ c     │       │
+└───────┴───────┘
+"
+        );
+        let rows: Vec<&str> = output
+            .lines()
+            .filter(|line| line.starts_with('│'))
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows.iter().all(|row| row.matches('│').count() == 3));
+    }
+
+    #[test]
+    fn test_ndjson_without_headers() {
+        let input = b"val1,val2\n".to_vec();
+        let expected = String::from("{\"0\":\"val1\",\"1\":\"val2\"}\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().output_format(OutputFormat::Ndjson);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_null_bytes() {
+        let (field, changes) = cleanse_field(b"hel\x00lo", &csv_opts(), 0, 0);
+        assert_eq!(field, "hel lo");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::NullByteReplacement]);
+    }
+
+    #[test]
+    fn test_trim() {
+        let opts = csv_opts().trim(true);
+        let (field, changes) = cleanse_field(b"  hello \t", &opts, 0, 0);
+        assert_eq!(field, "hello");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::WhitespaceTrimmed]);
+
+        let (field, changes) = cleanse_field(b"  ", &opts, 0, 0);
+        assert_eq!(field, "");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::WhitespaceTrimmed]);
+
+        let (field, changes) = cleanse_field(b"hello", &opts, 0, 0);
+        assert_eq!(field, "hello");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_max_errors_per_record() {
+        let input = b"a,b,c\n\"d,\",\"e\n\",\xff\n\"g,\",h,i\n".to_vec();
+        let expected = String::from("a,b,c\ng ,h,i\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().max_errors_per_record(Some(2));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.records_dropped, 1);
+    }
+
+    #[test]
+    fn test_quote_char() {
+        let input = b"a,'b,c',\"d\"\n".to_vec();
+        let expected = String::from("a,b c,\"d\"\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().quote(b'\'');
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_strips_bom() {
+        let path = std::env::temp_dir().join(format!("cleanse_bom_{}.csv", std::process::id()));
+        std::fs::write(&path, b"\xEF\xBB\xBFa,b\n").unwrap();
+
+        let mut writer = vec![];
+        let input = get_input(Some(path.clone()), InputEncoding::Utf8, None, false, None).unwrap();
+        run(input, &mut writer, &csv_opts()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("a,b\n", writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert_eq!(parse_byte_size("64KB"), Ok(64 * 1024));
+        assert_eq!(parse_byte_size("1MB"), Ok(1024 * 1024));
+        assert_eq!(parse_byte_size("4 mb"), Ok(4 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1g"), Ok(1024 * 1024 * 1024));
+        for invalid in ["", "0", "MB", "1TB", "1.5MB", "-1"] {
+            assert!(parse_byte_size(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    /// Accepts `limit` bytes, then fails every write as if the disk were full.
+    struct Full {
+        limit: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.limit == 0 {
+                return Err(io::Error::other("No space left on device"));
+            }
+            let len = buf.len().min(self.limit);
+            self.limit -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_finish_error() {
+        // Only the gzip header fits, the rest of the stream is written when it's finished
+        let mut output = Output::Gzip(BufWriter::new(GzEncoder::new(
+            Box::new(Full { limit: 10 }) as Box<dyn Write>,
+            6,
+        )));
+        output.write_all(b"a").unwrap();
+        assert!(output.finish().is_err());
+
+        let mut output = Output::Plain(BufWriter::new(Box::new(Full { limit: 0 })));
+        output.write_all(b"a").unwrap();
+        assert!(output.finish().is_err());
+    }
+
+    #[test]
+    fn test_compression_from_extension() {
+        let path = std::env::temp_dir().join(format!("cleanse_gz_{}.csv.gz", std::process::id()));
+        let mut output = get_output(Some(path.clone()), None, Some(9), None).unwrap();
+        run(&b"\"a,b\",c\n"[..], &mut output, &csv_opts()).unwrap();
+        output.finish().unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(&[0x1f, 0x8b]));
+
+        let mut writer = vec![];
+        let input = get_input(Some(path.clone()), InputEncoding::Utf8, None, false, None).unwrap();
+        run(input, &mut writer, &csv_opts()).unwrap();
+        assert_eq!("a b,c\n", writer.into_string().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        // Unsupported compressions fail rather than being read or written as plain bytes
+        let path = path.with_extension("zst");
+        assert!(get_output(Some(path.clone()), None, None, None).is_err());
+        assert!(!path.exists());
+        std::fs::write(&path, b"a,b\n").unwrap();
+        let input = get_input(Some(path.clone()), InputEncoding::Utf8, None, false, None);
+        std::fs::remove_file(&path).unwrap();
+        assert!(input.is_err());
+    }
+
+    #[test]
+    fn test_mmap_input() {
+        let path = std::env::temp_dir().join(format!("cleanse_mmap_{}.csv", std::process::id()));
+        let mut input = vec![];
+        for i in 0..50_000 {
+            match i % 3 {
+                0 => input.extend_from_slice(b"\"has,a delimiter\",2,3\n"),
+                1 => input.extend_from_slice(b"\"has a\nnewline\",caf\xe9,3\n"),
+                _ => input.extend_from_slice(b"clean field,2,3\n"),
+            }
+        }
+        std::fs::write(&path, &input).unwrap();
+
+        let mut outputs = vec![];
+        for mmap in [false, true] {
+            let mut writer = vec![];
+            let input =
+                get_input(Some(path.clone()), InputEncoding::Utf8, None, mmap, None).unwrap();
+            let stats = run(input, &mut writer, &csv_opts()).unwrap();
+            assert_eq!(stats.records, 50_000);
+            outputs.push(writer);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(outputs[0], outputs[1]);
+
+        assert!(get_input(None, InputEncoding::Utf8, None, true, None).is_err());
+    }
+
+    #[test]
+    fn test_detect_encoding_input() {
+        let path = std::env::temp_dir().join(format!("cleanse_detect_{}.csv", std::process::id()));
+        std::fs::write(&path, b"name,city\n\"Zo\xeb\",\"K\xf6ln,DE\"\n").unwrap();
+
+        let mut writer = vec![];
+        let input =
+            get_input(Some(path.clone()), InputEncoding::Detect, None, false, None).unwrap();
+        let stats = run(input, &mut writer, &csv_opts()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("name,city\nZoë,Köln DE\n", writer.into_string().unwrap());
+        assert_eq!(stats.encoding_fixes, 0);
+    }
+
+    #[test]
+    fn test_utf16_input() {
+        let path = std::env::temp_dir().join(format!("cleanse_utf16_{}.csv", std::process::id()));
+        let mut bytes = b"\xFF\xFE".to_vec();
+        bytes.extend(
+            "name,city\n\"Zoë\",\"Köln,DE\"\n"
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes),
+        );
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut writer = vec![];
+        let input = get_input(Some(path.clone()), InputEncoding::Utf8, None, false, None).unwrap();
+        run(input, &mut writer, &csv_opts()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("name,city\nZoë,Köln DE\n", writer.into_string().unwrap());
+    }
+
+    #[test]
+    fn test_columns() {
+        let input = b"\"a,b\",\"c,d\",\"e,f\"\n".to_vec();
+        let expected = String::from("\"a,b\",c d,\"e,f\"\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().columns(Some(parse_columns("2").unwrap()));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.delimiter_replacements, 1);
+
+        // Out of range columns only warn
+        let record = ByteRecord::from(vec!["g,h"]);
+        let (cleansed, changes, _) = cleanse_record(&record, &opts, 1);
+        assert_eq!(cleansed, record);
+        assert!(changes.is_empty());
+
+        assert_eq!(
+            parse_columns("2, 4")
+                .unwrap()
+                .indices
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+        assert!(parse_columns("0").is_err());
+        assert!(parse_columns("a").is_err());
+    }
+
+    #[test]
+    fn test_column_ranges() {
+        let input = b"\"a,1\",\"b,2\",\"c,3\",\"d,4\",\"e,5\"\n".to_vec();
+        let expected = String::from("\"a,1\",b 2,c 3,d 4,\"e,5\"\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().columns(Some(parse_columns("2-4").unwrap()));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.delimiter_replacements, 3);
+
+        let columns = parse_columns("-2,5,8-").unwrap();
+        let selected: Vec<usize> = (1..=10).filter(|&c| columns.contains(c)).collect();
+        assert_eq!(selected, vec![1, 2, 5, 8, 9, 10]);
+        assert_eq!(columns.min_fields(), Some(8));
+        assert_eq!(parse_columns("1-10,20").unwrap().min_fields(), Some(20));
+        assert!(parse_columns("4-2").is_err());
+        assert!(parse_columns("0-2").is_err());
+        assert!(parse_columns("1-a").is_err());
+    }
+
+    #[test]
+    fn test_expected_fields() {
+        let input = b"a,b\nc,d,e\nf,g\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().expected_fields(Some(3));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nc,d,e\nf,g\n");
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.field_count_mismatches, 2);
+
+        let opts = csv_opts().expected_fields(Some(2)).strict_fields(true);
+        let err = run(input.as_slice(), &mut vec![], &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Record number 1: expected 2 fields, found 3"
+        );
+
+        let input = b"a,b\n".to_vec();
+        let opts = csv_opts().expected_fields(Some(3));
+        let stats = run(input.as_slice(), &mut vec![], &opts).unwrap();
+        assert_eq!(stats.field_count_mismatches, 1);
+    }
+
+    #[test]
+    fn test_no_encoding_fix() {
+        let input = b"a,b\nc,\"d,e\"\nf,g\xff\n".to_vec();
+
+        let opts = csv_opts().no_encoding_fix(true);
+        let err = run(input.as_slice(), &mut vec![], &opts).unwrap_err();
+        assert!(matches!(
+            err,
+            CleanseError::EncodingError {
+                record: 2,
+                field: 1
+            }
+        ));
+
+        let mut writer = vec![];
+        let stats = run(&b"a,\"b,c\"\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b c\n");
+        assert_eq!(stats.encoding_fixes, 0);
+    }
+
+    #[test]
+    fn test_no_delimiter_fix() {
+        let input = b"a,\"b,c\",\"d\ne\"\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().no_delimiter_fix(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,\"b,c\",d e\n");
+        assert_eq!(stats.delimiter_replacements, 0);
+        assert_eq!(stats.terminator_replacements, 1);
+    }
+
+    #[test]
+    fn test_no_terminator_fix() {
+        let opts = csv_opts().no_terminator_fix(true);
+        let (cleaned, changes) = cleanse_field(b"a\n very, gross\r\n", &opts, 0, 0);
+        assert_eq!(cleaned, "a\n very  gross\r\n");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::DelimiterReplacement]);
+
+        // Kept even when stripping other control characters
+        let (cleaned, _) = cleanse_field(b"a\n\x01b", &opts.clone().strip_control(true), 0, 0);
+        assert_eq!(cleaned, "a\n b");
+
+        let mut writer = vec![];
+        run(&b"\"a\nb\",c\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "\"a\nb\",c\n");
+    }
+
+    #[test]
+    fn test_allow_pattern() {
+        let input = b"1,a,2021-08-18\n2,b,not-a-date\n3,c,2021-08-20\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .allow_patterns(vec![
+                r"3:^\d{4}-\d{2}-\d{2}$".parse().unwrap(),
+                "1:^\\d+$".parse().unwrap(),
+            ])
+            .collect_changes(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer, input);
+        assert_eq!(stats.pattern_violations, 1);
+        assert_eq!(
+            (stats.violations[0].record, stats.violations[0].field),
+            (1, 2)
+        );
+        // Violations aren't changes
+        assert!(stats.changes.is_empty());
+        assert_eq!((stats.fields_changed, stats.records_changed), (0, 0));
+        assert!(!stats.has_changes());
+
+        // Columns left out of --columns are still checked
+        let opts = opts.columns(Some(parse_columns("1").unwrap()));
+        let stats = run(input.as_slice(), &mut vec![], &opts).unwrap();
+        assert_eq!(stats.pattern_violations, 1);
+
+        assert!("0:a".parse::<AllowPattern>().is_err());
+        assert!("a".parse::<AllowPattern>().is_err());
+        assert!("1:(".parse::<AllowPattern>().is_err());
+    }
+
+    #[test]
+    fn test_field_count_range() {
+        let input = b"a,b\na,b,c\na,b,c,d\na,b,c,d,e\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().min_fields(Some(3)).max_fields(Some(4));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            input.clone().into_string().unwrap()
+        );
+        assert_eq!(stats.records, 4);
+        assert_eq!(stats.field_count_under, 1);
+        assert_eq!(stats.field_count_over, 1);
+
+        let opts = csv_opts().min_fields(Some(3)).strict_fields(true);
+        let err = run(input.as_slice(), &mut vec![], &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Record number 0: expected at least 3 fields, found 2"
+        );
+
+        let opts = csv_opts().max_fields(Some(4)).strict_fields(true);
+        let err = run(input.as_slice(), &mut vec![], &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Record number 3: expected at most 4 fields, found 5"
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let input = b"a,b\nc,d,e\nf,g\n".to_vec();
+
+        let err = run(input.as_slice(), &mut vec![], &csv_opts()).unwrap_err();
+        assert!(matches!(err, CleanseError::Csv(_)));
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .skip_malformed(true)
+            .collect_changes(true)
+            .batch_size(2);
+        let input = b"a,b\nc,d,e\n\"f,\",g\nh,i\n".to_vec();
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nf ,g\nh,i\n");
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.malformed_records, 1);
+        // The skipped record still takes up a record number
+        assert_eq!(stats.changes[0].record, 2);
+    }
+
+    #[test]
+    fn test_rename() {
+        assert_eq!(
+            "FK_CUST_ID:customer_id".parse::<ColumnRename>().unwrap(),
+            ColumnRename {
+                from: String::from("FK_CUST_ID"),
+                to: String::from("customer_id")
+            }
+        );
+        assert!("FK_CUST_ID".parse::<ColumnRename>().is_err());
+        assert!(":customer_id".parse::<ColumnRename>().is_err());
+
+        let opts = csv_opts().has_headers(true).renames(vec![
+            "FK_CUST_ID:customer_id".parse().unwrap(),
+            "b:c".parse().unwrap(),
+            "missing:x".parse().unwrap(),
+        ]);
+        let input = b"FK_CUST_ID,b\n1,2\n".to_vec();
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "customer_id,c\n1,2\n");
+    }
+
+    #[test]
+    fn test_header_case() {
+        let input = "FIRST_NAME,first_name,First_Näme\nA,b,C\n"
+            .as_bytes()
+            .to_vec();
+        for (case, expected) in [
+            (
+                HeaderCase::Lower,
+                "first_name,first_name,first_näme\nA,b,C\n",
+            ),
+            (
+                HeaderCase::Upper,
+                "FIRST_NAME,FIRST_NAME,FIRST_NÄME\nA,b,C\n",
+            ),
+        ]
+        .iter()
+        {
+            let opts = csv_opts().has_headers(true).header_case(Some(*case));
+            let mut writer = vec![];
+            run(input.as_slice(), &mut writer, &opts).unwrap();
+            assert_eq!(writer.into_string().unwrap(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_headers() {
+        let headers = ByteRecord::from(vec!["id", "name", "name", "value", "id", "name"]);
+        let duplicates: Vec<String> = duplicate_headers(&headers)
+            .iter()
+            .map(|duplicate| duplicate.to_string())
+            .collect();
+        assert_eq!(
+            duplicates,
+            vec![
+                "Duplicate header \"id\" in columns 0, 4",
+                "Duplicate header \"name\" in columns 1, 2, 5"
+            ]
+        );
+
+        let input = b"id,name,name,value\n1,a,b,c\n".to_vec();
+        let opts = csv_opts().has_headers(true);
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "id,name,name,value\n1,a,b,c\n"
+        );
+        let err = run(input.as_slice(), &mut vec![], &opts.strict_fields(true)).unwrap_err();
+        assert!(matches!(
+            err,
+            CleanseError::DuplicateHeader { columns, .. } if columns == vec![1, 2]
+        ));
+    }
+
+    #[test]
+    fn test_strip_trailing_delimiter() {
+        let mut writer = vec![];
+        let opts = csv_opts().strip_trailing_delimiter(true);
+        let input = b"a,b,c,\nd,,\n,\n".to_vec();
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        // Only the one empty field after the last delimiter is dropped
+        assert_eq!(writer.into_string().unwrap(), "a,b,c\nd,\n\"\"\n");
+
+        // A quoted empty last field is kept
+        let mut writer = vec![];
+        let input = b"a,b,\"\"\r\nc,d,\r\ne,f,\"\"".to_vec();
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b,\nc,d\ne,f,\n");
+    }
+
+    #[test]
+    fn test_raw_split_malformed() {
+        let mut writer = vec![];
+        let opts = csv_opts().raw_split_malformed(true);
+        let input = b"a,b\nc,\"d,e\",f\ng,h\n".to_vec();
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        // The quoting is lost along with the parse, so the quoted delimiter splits the field
+        assert_eq!(writer.into_string().unwrap(), "a,b\nc,d,e,f\ng,h\n");
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.raw_split_records, 1);
+        assert_eq!(stats.malformed_records, 0);
+    }
+
+    #[test]
+    fn test_skip_lines() {
+        let input =
+            b"Exported 2021-08-18\nsource: \"db\", rows: 2\nh1,h2\na,\"b,c\"\nd,e\n".to_vec();
+        let expected = String::from("h1,h2\na,b c\nd,e\n");
+
+        let mut writer = vec![];
+        let opts = csv_opts().skip_lines(2).has_headers(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(expected, writer.into_string().unwrap());
+        assert_eq!(stats.records, 2);
+
+        let mut reader = &b"a\nb"[..];
+        assert_eq!(skip_lines(&mut reader, 5).unwrap(), 1);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_output_line_ending() {
+        let input = b"h1,h2\r\na,\"b\nc\"\r\n".to_vec();
+        for (line_ending, expected) in [
+            (LineEnding::Crlf, &b"h1,h2\r\na,b c\r\n"[..]),
+            (LineEnding::Lf, b"h1,h2\na,b c\n"),
+        ] {
+            let mut writer = vec![];
+            let opts = csv_opts()
+                .has_headers(true)
+                .output_line_ending(Some(line_ending));
+            run(input.as_slice(), &mut writer, &opts).unwrap();
+            assert_eq!(writer, expected);
+        }
+    }
+
+    #[test]
+    fn test_delimited_format() {
+        for (name, delimiter) in [("csv", b','), ("TSV", b'\t'), ("psv", b'|'), ("ssv", b';')] {
+            assert_eq!(
+                name.parse::<DelimitedFormat>().unwrap().delimiter(),
+                delimiter
+            );
+        }
+        assert!("xsv".parse::<DelimitedFormat>().is_err());
+    }
+
+    #[test]
+    fn test_auto_detect() {
+        assert_eq!(detect_delimiter(b"a|b|c;d\n"), Some(b'|'));
+        assert_eq!(detect_delimiter(b"a:b\tc\n"), Some(b'\t'));
+        assert_eq!(detect_delimiter(b"abc\n"), None);
+
+        let input = b"a;b;\"c;d\"\ne;f;g\n".to_vec();
+        let mut writer = vec![];
+        let opts = csv_opts().auto_detect(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a;b;c d\ne;f;g\n");
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.delimiter_replacements, 1);
+
+        // Without any candidates the given delimiter is used
+        let input = b"ab\ncd\n".to_vec();
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "ab\ncd\n");
+    }
+
+    #[test]
+    fn test_empty_field_replacement() {
+        let input = b"a,,c\n,\"\",\" \"\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().empty_replacement(Some(String::from("NULL")));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,NULL,c\nNULL,NULL, \n");
+        assert_eq!(stats.empty_field_replacements, 3);
+
+        // Fields emptied by trimming are replaced too
+        let opts = csv_opts()
+            .empty_replacement(Some(String::from("NULL")))
+            .trim(true);
+        let (cleaned, changes) = cleanse_field(b" ", &opts, 0, 0);
+        assert_eq!(cleaned, "NULL");
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                CleanseChanges::WhitespaceTrimmed,
+                CleanseChanges::EmptyFieldReplaced
+            ]
+        );
+
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &csv_opts()).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,,c\n,, \n");
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        let opts = csv_opts().collapse_whitespace(true);
+        let (cleaned, changes) = cleanse_field(b"a   b", &opts, 0, 0);
+        assert_eq!(cleaned, "a b");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::WhitespaceCollapsed]);
+
+        let (cleaned, changes) = cleanse_field(b"a\tb c \t d", &opts, 0, 0);
+        assert_eq!(cleaned, "a\tb c d");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::WhitespaceCollapsed]);
+
+        let (cleaned, changes) = cleanse_field(b"a b c", &opts, 0, 0);
+        assert_eq!(cleaned, "a b c");
+        assert!(changes.is_empty());
+
+        // Runs made by replacing terminators are collapsed too
+        let (cleaned, changes) = cleanse_field(b"a \r\n\nb", &opts, 0, 0);
+        assert_eq!(cleaned, "a b");
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                CleanseChanges::CarriageReturnReplacement,
+                CleanseChanges::TerminatorReplacement,
+                CleanseChanges::WhitespaceCollapsed
+            ]
+        );
+
+        // Edges that are trimmed are not also collapsed
+        let opts = opts.trim(true);
+        let (cleaned, changes) = cleanse_field(b"  a  ", &opts, 0, 0);
+        assert_eq!(cleaned, "a");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::WhitespaceTrimmed]);
+    }
+
+    #[test]
+    fn test_normalize_numbers() {
+        for (input, expected) in [
+            ("$1,234.56", Some("1234.56")),
+            ("1 234,56", Some("1234.56")),
+            ("1,234,567", Some("1234567")),
+            ("1,234", Some("1234")),
+            ("12,5", Some("12.5")),
+            ("0,125", Some("0.125")),
+            ("-$12", Some("-12")),
+            ("+ 3.0 €", Some("3.0")),
+            ("007", Some("007")),
+            ("1.2.3", None),
+            ("1,2,3.4,5", Some("123.45")),
+            ("1e5", None),
+            ("abc", None),
+            ("$", None),
+            ("12-3", None),
+            ("", None),
+        ] {
+            assert_eq!(normalize_number(input).as_deref(), expected, "{}", input);
+        }
+
+        let opts = CleanseOptions::default().normalize_numbers(true);
+        let (cleaned, changes) = cleanse_field(b"$1,234.56", &opts, 0, 0);
+        assert_eq!(cleaned, "1234.56");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::NumericNormalized]);
+        let (cleaned, changes) = cleanse_field(b"1234.56", &opts, 0, 0);
+        assert_eq!(cleaned, "1234.56");
+        assert!(changes.is_empty());
+        let (cleaned, changes) = cleanse_field(b"Suite 1,200", &opts, 0, 0);
+        assert_eq!(cleaned, "Suite 1,200");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_max_records() {
+        let input = b"h\n1\n2\n3\n4\n5\n".to_vec();
+
+        for batch_size in [1, 2, 3, 1024] {
+            let mut writer = vec![];
+            let opts = csv_opts()
+                .max_records(Some(3))
+                .has_headers(true)
+                .batch_size(batch_size);
+            let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+            assert_eq!(writer.into_string().unwrap(), "h\n1\n2\n3\n");
+            assert_eq!(stats.records, 3);
+        }
+
+        let mut writer = vec![];
+        let opts = csv_opts().max_records(Some(0));
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert!(writer.is_empty());
+
+        // Skipped malformed records don't count towards the limit
+        let input = b"a,b\nc\nd,e\nf,g\nh,i\n".to_vec();
+        let mut writer = vec![];
+        let opts = csv_opts().max_records(Some(3)).skip_malformed(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nd,e\nf,g\n");
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.malformed_records, 1);
+    }
+
+    #[test]
+    fn test_skip_records() {
+        let input = b"h\n1\n\"2\n2\"\n3\n\"4,4\"\n5\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .skip_records(2)
+            .max_records(Some(2))
+            .has_headers(true)
+            .collect_changes(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "h\n3\n4 4\n");
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.records_skipped, 2);
+        assert_eq!(stats.changes[0].record, 3);
+
+        let mut writer = vec![];
+        let opts = csv_opts().skip_records(10);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert!(writer.is_empty());
+        assert_eq!(stats.records_skipped, 6);
+    }
+
+    #[test]
+    fn test_unique_key() {
+        let input = b"1,a,x\n2,b,y\n1,c,x\n".to_vec();
+        let opts = csv_opts()
+            .unique_key(Some(parse_columns("1,3").unwrap()))
+            .batch_size(2);
+        let mut writer = vec![];
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "1,a,x\n2,b,y\n1,c,x\n");
+        assert_eq!(stats.duplicate_keys, 1);
+
+        let mut writer = vec![];
+        let stats = run(input.as_slice(), &mut writer, &opts.drop_duplicates(true)).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "1,a,x\n2,b,y\n");
+        assert_eq!(stats.duplicate_keys, 1);
+
+        let columns = parse_columns("1,2").unwrap();
+        assert_ne!(
+            record_key(&ByteRecord::from(vec!["ab", "c"]), &columns),
+            record_key(&ByteRecord::from(vec!["a", "bc"]), &columns)
+        );
+    }
+
+    #[test]
+    fn test_dedup_adjacent() {
+        let input = b"a,\"b,c\"\na,\"b,c\"\nd,e\nd,e\na,\"b,c\"\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().dedup_adjacent(true).batch_size(3);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b c\nd,e\na,b c\n");
+        assert_eq!(stats.records, 5);
+        // Dropped records are counted in both
+        assert_eq!(stats.fields, 10);
+        assert_eq!(stats.adjacent_duplicates_dropped, 2);
+        assert_eq!(stats.delimiter_replacements, 2);
+    }
+
+    #[test]
+    fn test_remove_empty_records() {
+        let input = b"a,\"b,c\",d\n,\"\",\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().remove_empty_records(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b c,d\n");
+        assert_eq!(stats.records, 2);
+        assert_eq!(stats.empty_records_dropped, 1);
+    }
+
+    #[test]
+    fn test_strip_control() {
+        let opts = csv_opts().strip_control(true);
+        let (cleaned, changes) = cleanse_field(b"hel\x03lo", &opts, 0, 0);
+        assert_eq!(cleaned, "hel lo");
+        assert_eq!(
+            kinds(&changes),
+            vec![CleanseChanges::ControlCharReplacement]
+        );
+
+        let (cleaned, changes) = cleanse_field(b"\x02a\tb\x7F,c\n", &opts, 0, 0);
+        assert_eq!(cleaned, " a b  c ");
+        assert_eq!(
+            kinds(&changes),
+            vec![
+                CleanseChanges::DelimiterReplacement,
+                CleanseChanges::TerminatorReplacement,
+                CleanseChanges::ControlCharReplacement
+            ]
+        );
+
+        // Only stripped when asked for
+        let (cleaned, changes) = cleanse_field(b"hel\x03lo", &csv_opts(), 0, 0);
+        assert_eq!(cleaned, "hel\x03lo");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_hex_escape_control() {
+        let opts = csv_opts().hex_escape_control(true);
+        let (cleaned, changes) = cleanse_field(b"\x03", &opts, 0, 0);
+        assert_eq!(cleaned, "\\x03");
+        assert_eq!(
+            kinds(&changes),
+            vec![CleanseChanges::ControlCharReplacement]
+        );
+
+        let (cleaned, _) = cleanse_field(b"a\x1Fb\x7F\n", &opts, 0, 0);
+        assert_eq!(cleaned, "a\\x1Fb\\x7F ");
+    }
+
+    #[test]
+    fn test_max_field_length() {
+        let opts = csv_opts().max_field_length(Some(255));
+        let (field, changes) = cleanse_field(&[b'a'; 300], &opts, 0, 0);
+        assert_eq!(field.len(), 255);
+        assert_eq!(kinds(&changes), vec![CleanseChanges::FieldTruncated]);
+
+        let (field, changes) = cleanse_field(&[b'a'; 255], &opts, 0, 0);
+        assert_eq!(field.len(), 255);
+        assert!(changes.is_empty());
+
+        // Never split a multi-byte character
+        let opts = csv_opts().max_field_length(Some(4));
+        let (field, _) = cleanse_field("abcé".as_bytes(), &opts, 0, 0);
+        assert_eq!(field, "abc");
+    }
+
+    #[test]
+    fn test_max_field_length_trims() {
+        let opts = csv_opts().max_field_length(Some(4)).trim(true);
+        let (cleaned, changes) = cleanse_field(b"abc def", &opts, 0, 0);
+        assert_eq!(cleaned, "abc");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::FieldTruncated]);
+    }
+
+    #[test]
+    fn test_truncate_ellipsis() {
+        let opts = csv_opts().max_field_length(Some(5)).truncate_ellipsis(true);
+        let (cleaned, changes) = cleanse_field(b"hello world", &opts, 0, 0);
+        assert_eq!(cleaned, "he...");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::FieldTruncated]);
+
+        // Short enough fields are left alone
+        let (cleaned, changes) = cleanse_field(b"hello", &opts, 0, 0);
+        assert_eq!(cleaned, "hello");
+        assert!(changes.is_empty());
+
+        // Without splitting a character
+        let (cleaned, _) = cleanse_field("aééé".as_bytes(), &opts, 0, 0);
+        assert_eq!(cleaned, "a...");
+
+        let opts = csv_opts().max_field_length(Some(2)).truncate_ellipsis(true);
+        let (cleaned, _) = cleanse_field(b"hello", &opts, 0, 0);
+        assert_eq!(cleaned, "..");
+    }
+
+    #[test]
+    fn test_base64_non_utf8() {
+        let opts = csv_opts().base64_non_utf8(true);
+        let (cleaned, changes) = cleanse_field(b"\x89PNG,\r\n\x1a\n\xff", &opts, 0, 0);
+        assert_eq!(cleaned, "iVBORywNChoK/w==");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::Base64Encoded]);
+
+        // Valid UTF-8 is cleansed as usual
+        let (cleaned, changes) = cleanse_field(b"a,b", &opts, 0, 0);
+        assert_eq!(cleaned, "a b");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::DelimiterReplacement]);
+    }
+
+    #[test]
+    fn test_value_map() {
+        assert_eq!(
+            "2:N/A=".parse::<ValueMap>().unwrap(),
+            ValueMap {
+                column: 2,
+                from: String::from("N/A"),
+                to: String::new()
+            }
+        );
+        assert!("0:a=b".parse::<ValueMap>().is_err());
+        assert!("2:a".parse::<ValueMap>().is_err());
+
+        let opts =
+            csv_opts().value_maps(vec!["2:N/A=".parse().unwrap(), "2:TRUE=1".parse().unwrap()]);
+        let (cleaned, changes) = cleanse_field(b"N/A", &opts, 0, 1);
+        assert_eq!(cleaned, "");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::ValueMapped]);
+        let (cleaned, _) = cleanse_field(b"TRUE", &opts, 0, 1);
+        assert_eq!(cleaned, "1");
+        // Only exact matches in the given column are mapped
+        for (field, field_number) in [(&b"n/a"[..], 1), (b"N/A!", 1), (b"N/A", 0)].iter() {
+            let (cleaned, changes) = cleanse_field(field, &opts, 0, *field_number);
+            assert_eq!(cleaned.as_bytes(), *field);
+            assert!(changes.is_empty());
+        }
+
+        // Columns left out of --columns are still mapped
+        let opts = opts.columns(Some(parse_columns("1").unwrap()));
+        let mut writer = vec![];
+        let stats = run(&b"\"a,b\",N/A\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a b,\n");
+        assert_eq!(stats.value_maps, 1);
+    }
+
+    #[test]
+    fn test_required_fields() {
+        let opts = csv_opts().required_fields(Some(parse_columns("1").unwrap()));
+        let input = b"a,b\n,c\nd,\n".to_vec();
+        let mut writer = vec![];
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        // Empty required fields are only reported
+        assert_eq!(writer.into_string().unwrap(), "a,b\n,c\nd,\n");
+        assert_eq!(stats.required_field_violations, 1);
+
+        // Columns left out of --columns are still checked
+        let columns_opts = opts.clone().columns(Some(parse_columns("2").unwrap()));
+        let stats = run(input.as_slice(), &mut vec![], &columns_opts).unwrap();
+        assert_eq!(stats.required_field_violations, 1);
+
+        let err = run(input.as_slice(), &mut vec![], &opts.strict_fields(true)).unwrap_err();
+        assert!(matches!(
+            err,
+            CleanseError::RequiredFieldEmpty {
+                record: 1,
+                field: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn test_normalize_unicode() {
+        let opts = csv_opts().normalize_unicode(Some(UnicodeNormalization::Nfc));
+        let (cleaned, changes) = cleanse_field("e\u{301}".as_bytes(), &opts, 0, 0);
+        assert_eq!(cleaned, "\u{e9}");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::UnicodeNormalized]);
+        // Already normalized fields aren't changed
+        let (cleaned, changes) = cleanse_field("\u{e9}".as_bytes(), &opts, 0, 0);
+        assert_eq!(cleaned, "\u{e9}");
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_hex_non_utf8() {
+        let opts = csv_opts().hex_non_utf8(true);
+        let (cleaned, changes) = cleanse_field(b"\xff", &opts, 0, 0);
+        assert_eq!(cleaned, "\\xFF");
+        assert_eq!(kinds(&changes), vec![CleanseChanges::HexEncoded]);
+
+        let (cleaned, _) = cleanse_field(b"caf\xe9 \xe2\x82 \xc3\xa9", &opts, 0, 0);
+        assert_eq!(cleaned, "caf\\xE9 \\xE2\\x82 é");
+    }
+
+    #[test]
+    fn test_split_records() {
+        use std::fs;
+
+        let dir = std::env::temp_dir().join(format!("cleanse_split_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.csv");
+        let input = b"h1,h2\n1,a\n2,\"b,c\"\n3,d\n4,e\n5,f\n".to_vec();
+
+        let opts = csv_opts().has_headers(true).split_records(Some(2));
+        let stats = run_split(
+            input.as_slice(),
+            |chunk| get_output(Some(chunk_path(&path, chunk)), None, None, None),
+            Output::finish,
+            &mut SideOutputs::default(),
+            &opts,
+        )
+        .unwrap();
+        assert_eq!(stats.records, 5);
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["out_000.csv", "out_001.csv", "out_002.csv"]);
+        let read = |chunk| fs::read_to_string(chunk_path(&path, chunk)).unwrap();
+        assert_eq!(read(0), "h1,h2\n1,a\n2,b c\n");
+        assert_eq!(read(1), "h1,h2\n3,d\n4,e\n");
+        assert_eq!(read(2), "h1,h2\n5,f\n");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            chunk_path(Path::new("dir/out.csv.gz"), 12),
+            Path::new("dir/out_012.csv.gz")
+        );
+        assert_eq!(
+            chunk_path(Path::new(".hidden"), 1),
+            Path::new(".hidden_001")
+        );
+    }
+
+    #[test]
+    fn test_add_record_number() {
+        let input = b"a,b\n\"c,d\",e\nf,g\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().has_headers(true).add_record_number(true);
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "_record_number,a,b\n1,c d,e\n2,f,g\n"
+        );
+
+        // Numbering continues across inputs
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .add_record_number(true)
+            .first_record_number(5)
+            .record_number_header(String::from("ignored"));
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "6,a,b\n7,c d,e\n8,f,g\n");
+    }
+
+    #[test]
+    fn test_drop_columns() {
+        let input = b"h1,h2,h3,h4\na,b,\"c,d\",e\nf,g,h,i\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .has_headers(true)
+            .drop_columns(Some(parse_columns("2").unwrap()));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "h1,h3,h4\na,c d,e\nf,h,i\n");
+        assert_eq!(stats.delimiter_replacements, 1);
+
+        // Dropped columns aren't cleansed
+        let mut writer = vec![];
+        let opts = csv_opts().drop_columns(Some(parse_columns("3-").unwrap()));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "h1,h2\na,b\nf,g\n");
+        assert_eq!(stats.delimiter_replacements, 0);
+    }
+
+    #[test]
+    fn test_split_field() {
+        let split_field: SplitField = "3:, :last_name:first_name".parse().unwrap();
+        assert_eq!(
+            split_field,
+            SplitField {
+                column: 3,
+                separator: String::from(", "),
+                first_name: String::from("last_name"),
+                second_name: String::from("first_name"),
+            }
+        );
+        assert_eq!("1:::a:b".parse::<SplitField>().unwrap().separator, ":");
+        assert!("3::a:b".parse::<SplitField>().is_err());
+        assert!("0:,:a:b".parse::<SplitField>().is_err());
+        assert!("3:,:a".parse::<SplitField>().is_err());
+
+        let opts = csv_opts()
+            .has_headers(true)
+            .split_field(Some("2:;:last_name:first_name".parse().unwrap()));
+        let input = b"id,name,age\n1,Smith;John,30\n2,Doe,40\n3,a;b;c,50\n".to_vec();
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "id,last_name,first_name,age\n1,Smith,John,30\n2,Doe,,40\n3,a,b;c,50\n"
+        );
+
+        // The column is split to match the header even if it's left out of --columns
+        let opts = opts.columns(Some(parse_columns("3").unwrap()));
+        let mut writer = vec![];
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            writer.into_string().unwrap(),
+            "id,last_name,first_name,age\n1,Smith,John,30\n2,Doe,,40\n3,a,b;c,50\n"
+        );
+    }
+
+    #[test]
+    fn test_reorder_columns() {
+        let input = b"a,b,\"c,d\"\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().reorder_columns(Some("3,1,2".parse().unwrap()));
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "c d,a,b\n");
+
+        // After dropping columns
+        let mut writer = vec![];
+        let opts = opts.drop_columns(Some(parse_columns("1").unwrap()));
+        let err = run(input.as_slice(), &mut writer, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Record number 0: expected 3 fields, found 2"
+        );
+        let opts = opts.reorder_columns(Some("2,1".parse().unwrap()));
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "c d,b\n");
+
+        for (order, err) in [
+            ("1,3,3", "Column 3 is listed more than once"),
+            ("1,3", "Column 2 is missing"),
+            ("0,1", "Column indices start at 1"),
+            ("1,x", "Invalid column index \"x\""),
+        ] {
+            assert_eq!(order.parse::<ColumnOrder>().unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn test_change_log() {
+        let path = std::env::temp_dir().join(format!("cleanse_log_{}.tsv", std::process::id()));
+        let input = b"a,\"b,c\"\n\"d\ne\",f\ng,h\n".to_vec();
+
+        let mut writer = vec![];
+        let mut side_outputs = SideOutputs {
+            change_log: Some(
+                ChangeLog::new(Box::new(
+                    get_output(Some(path.clone()), None, None, None).unwrap(),
+                ))
+                .unwrap(),
+            ),
+            error_output: None,
+        };
+        run_with_side_outputs(
+            input.as_slice(),
+            &mut writer,
+            &mut side_outputs,
+            &csv_opts(),
+        )
+        .unwrap();
+        drop(side_outputs);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            log,
+            "record_number\tfield_number\tchange_type\toriginal_bytes_hex\tnew_value\n\
+             0\t1\tdelimiter_replacement\t622c63\tb c\n\
+             1\t0\tterminator_replacement\t640a65\td e\n"
+        );
+    }
+
+    #[test]
+    fn test_max_change_ratio() {
+        // 2 of the 6 fields are changed
+        let input = b"a,b\nc,\"d,e\"\n\"f\ng\",h\n".to_vec();
+        let opts = csv_opts().has_headers(true).max_change_ratio(Some(0.3));
+        let mut writer = vec![];
+        let err = run(input.as_slice(), &mut writer, &opts).unwrap_err();
+        assert!(matches!(
+            err,
+            CleanseError::ChangeRatioExceeded { records: 2, ratio, .. } if ratio == 0.5
+        ));
+        assert_eq!(
+            err.to_string(),
+            "50.0% of the fields in the first 2 records would be changed, more than the maximum of \
+             30.0%"
+        );
+        assert!(writer.is_empty());
+
+        let mut writer = vec![];
+        let opts = opts.max_change_ratio(Some(0.5));
+        run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nc,d e\nf g,h\n");
+
+        // The header is still written without any records to check
+        let mut writer = vec![];
+        run(&b"a,b\n"[..], &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\n");
+
+        // A skipped malformed record ends the first batch, the sample carries on past it
+        let input = b"a,b\nc,d\ne\n\"f\ng\",\"h\ni\"\n".to_vec();
+        let opts = opts.skip_malformed(true).collect_changes(true);
+        let mut writer = vec![];
+        let err = run(
+            input.as_slice(),
+            &mut writer,
+            &opts.clone().max_change_ratio(Some(0.3)),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            CleanseError::ChangeRatioExceeded { records: 2, ratio, .. } if ratio == 0.5
+        ));
+        assert!(writer.is_empty());
+
+        let mut writer = vec![];
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nc,d\nf g,h i\n");
+        assert_eq!(stats.records, 2);
+        // The held records keep their record numbers
+        assert_eq!(stats.changes[0].record, 2);
+    }
+
+    #[test]
+    fn test_error_output() {
+        let path = std::env::temp_dir().join(format!("cleanse_errors_{}.csv", std::process::id()));
+        let input = b"a,b\nc,\"d,e\"\nf,g\n\"h\ni\",j\n".to_vec();
+        let opts = csv_opts().has_headers(true);
+
+        let mut side_outputs = SideOutputs {
+            error_output: Some(ErrorOutput::new(Box::new(
+                get_output(Some(path.clone()), None, None, None).unwrap(),
+            ))),
+            ..SideOutputs::default()
+        };
+        let mut writer = vec![];
+        let stats =
+            run_with_side_outputs(input.as_slice(), &mut writer, &mut side_outputs, &opts).unwrap();
+        drop(side_outputs);
+
+        let errors = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nc,d e\nf,g\nh i,j\n");
+        assert_eq!(errors, "a,b\nc,d e\nh i,j\n");
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.records_written_to_error_output, 2);
+
+        // Written with the detected delimiter, like the main output
+        let mut side_outputs = SideOutputs {
+            error_output: Some(ErrorOutput::new(Box::new(
+                get_output(Some(path.clone()), None, None, None).unwrap(),
+            ))),
+            ..SideOutputs::default()
+        };
+        let opts = opts.auto_detect(true);
+        let input = &b"a;b\nc;\"d;e\"\nf;g\n"[..];
+        run_with_side_outputs(input, vec![], &mut side_outputs, &opts).unwrap();
+        drop(side_outputs);
+        let errors = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(errors, "a;b\nc;d e\n");
+    }
+
+    #[test]
+    fn test_error_output_dropped_records() {
+        let path =
+            std::env::temp_dir().join(format!("cleanse_dropped_errors_{}.csv", std::process::id()));
+        let input = b"a,b,c\n\"d,\",\"e\n\",\xff\n\"g,\",h,i\n".to_vec();
+        let opts = csv_opts().max_errors_per_record(Some(2));
+
+        let mut side_outputs = SideOutputs {
+            error_output: Some(ErrorOutput::new(Box::new(
+                get_output(Some(path.clone()), None, None, None).unwrap(),
+            ))),
+            ..SideOutputs::default()
+        };
+        let mut writer = vec![];
+        let stats =
+            run_with_side_outputs(input.as_slice(), &mut writer, &mut side_outputs, &opts).unwrap();
+        drop(side_outputs);
+
+        let errors = std::fs::read_to_string(&path).unwrap();
+        // The dropped record is only in the error output
+        assert_eq!(writer.into_string().unwrap(), "a,b,c\ng ,h,i\n");
+        assert_eq!(errors, "d ,e ,\u{fffd}\ng ,h,i\n");
+        assert_eq!(stats.records_dropped, 1);
+        assert_eq!(stats.records_written_to_error_output, 2);
+
+        let mut side_outputs = SideOutputs {
+            error_output: Some(ErrorOutput::new(Box::new(
+                get_output(Some(path.clone()), None, None, None).unwrap(),
+            ))),
+            ..SideOutputs::default()
+        };
+        let input = &b"a,b\nc,\"d\ne\"\nc,\"d\ne\"\n"[..];
+        let opts = csv_opts().dedup_adjacent(true);
+        let mut writer = vec![];
+        let stats = run_with_side_outputs(input, &mut writer, &mut side_outputs, &opts).unwrap();
+        drop(side_outputs);
+        let errors = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b\nc,d e\n");
+        assert_eq!(errors, "c,d e\nc,d e\n");
+        assert_eq!(stats.records_written_to_error_output, 2);
+    }
+
+    #[test]
+    fn test_display_changes() {
+        assert_eq!(
+            CleanseChanges::DelimiterReplacement.to_string(),
+            "delimiter replaced"
+        );
+        assert_eq!(
+            CleanseChanges::FixedEncoding.to_string(),
+            "non-UTF-8 bytes fixed"
+        );
+        assert_eq!(
+            format!("{:?}", CleanseChanges::FixedEncoding),
+            "FixedEncoding"
+        );
+    }
+
+    #[test]
+    fn test_has_changes() {
+        let mut writer = vec![];
+        let stats = run(&b"a,b\n"[..], &mut writer, &csv_opts()).unwrap();
+        assert!(!stats.has_changes());
+
+        let stats = run(&b"a,\"b,c\"\n"[..], &mut writer, &csv_opts()).unwrap();
+        assert!(stats.has_changes());
+    }
+
+    #[test]
+    fn test_collect_changes() {
+        let input = b"a,\"b\nc\"\n\"d,\xffe\",f\n".to_vec();
+
+        let mut writer = vec![];
+        let opts = csv_opts().collect_changes(true);
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(
+            stats.changes,
+            vec![
+                FieldChange {
+                    record: 0,
+                    field: 1,
+                    kind: CleanseChanges::TerminatorReplacement,
+                    original: b"b\nc".to_vec(),
+                    cleaned: String::from("b c"),
+                },
+                FieldChange {
+                    record: 1,
+                    field: 0,
+                    kind: CleanseChanges::DelimiterReplacement,
+                    original: b"d,\xffe".to_vec(),
+                    cleaned: String::from("d \u{FFFD}e"),
+                },
+                FieldChange {
+                    record: 1,
+                    field: 0,
+                    kind: CleanseChanges::FixedEncoding,
+                    original: b"d,\xffe".to_vec(),
+                    cleaned: String::from("d \u{FFFD}e"),
+                },
+            ]
+        );
+
+        // Changes are only kept on request
+        let stats = run(input.as_slice(), &mut writer, &csv_opts()).unwrap();
+        assert!(stats.changes.is_empty());
+        assert_eq!(stats.total_changes(), 3);
+    }
+
+    #[test]
+    fn test_watch() {
+        let input = b"a,\"b,c\"\nd,e\n".to_vec();
+        let mut writer = vec![];
+        let opts = csv_opts()
+            .watch_interval(Some(Duration::from_millis(1)))
+            .max_records(Some(2));
+        let stats = run(input.as_slice(), &mut writer, &opts).unwrap();
+        assert_eq!(writer.into_string().unwrap(), "a,b c\nd,e\n");
+        assert_eq!(stats.records, 2);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let input = b"id,name,notes\n1,alice,\n22,bob,\"a,b\"\n".to_vec();
+        let opts = csv_opts().has_headers(true).statistics(true);
+        let stats = run(input.as_slice(), &mut vec![], &opts).unwrap();
+        let mut table = vec![];
+        stats.write_column_stats(&mut table).unwrap();
+        assert_eq!(
+            table.into_string().unwrap(),
+            "column\tnon_empty\tmax_length\nid\t2\t2\nname\t2\t5\nnotes\t1\t3\n"
+        );
+
+        // Without a header, columns are named by index, and runs are merged column by column
+        let opts = csv_opts().statistics(true);
+        let mut stats = run(&b"a,\n"[..], &mut vec![], &opts).unwrap();
+        stats.merge(&run(&b"bcd,e,f\n"[..], &mut vec![], &opts).unwrap());
+        let mut table = vec![];
+        stats.write_column_stats(&mut table).unwrap();
+        assert_eq!(
+            table.into_string().unwrap(),
+            "column\tnon_empty\tmax_length\n0\t2\t3\n1\t1\t1\n2\t1\t1\n"
+        );
+    }
+
+    #[test]
+    fn test_change_histogram() {
+        let input = b"\"a,b\",\"c,d\",\"e\nf\"\n\"g,h\",i,j\n".to_vec();
+        let stats = run(input.as_slice(), &mut vec![], &csv_opts()).unwrap();
+        let mut histogram = vec![];
+        stats.write_change_histogram(&mut histogram).unwrap();
+        let bar = "#".repeat(HISTOGRAM_WIDTH);
+        assert_eq!(
+            histogram.into_string().unwrap(),
+            format!(
+                "delimiter_replacement  3 {}\nterminator_replacement 1 {}\n",
+                bar,
+                &bar[..13]
+            )
+        );
+
+        let mut histogram = vec![];
+        RunStats::default()
+            .write_change_histogram(&mut histogram)
+            .unwrap();
+        assert!(histogram.is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let change = FieldChange {
+            record: 1,
+            field: 0,
+            kind: CleanseChanges::FixedEncoding,
+            original: b"d,\xffe\n".to_vec(),
+            cleaned: String::from("d \u{FFFD}e "),
+        };
+        assert_eq!(
+            change.diff(),
+            "[record 1, field 0] \"d,\\xFFe\\n\" -> \"d \u{FFFD}e \""
+        );
+    }
+}
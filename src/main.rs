@@ -1,171 +1,930 @@
-use bstr::{ByteSlice, ByteVec};
+use cleanse::change_log::ChangeLog;
+use cleanse::compression::Compression;
+use cleanse::config::{Config, ConfigValue};
+use cleanse::directory::find_files;
+use cleanse::encoding::InputEncoding;
+use cleanse::error_output::ErrorOutput;
+use cleanse::fixed_width::FixedWidths;
+use cleanse::normalize::UnicodeNormalization;
+use cleanse::output::{LineEnding, OutputFormat, SharedOutput};
+use cleanse::{
+    chunk_path, get_input, get_output, parse_byte_size, parse_columns, parse_quote_style,
+    run_shared, run_split, AllowPattern, CleanseError, CleanseOptions, ColumnOrder, ColumnRename,
+    Columns, DelimitedFormat, HeaderCase, Output, RegexReplace, RunStats, SideOutputs, SplitField,
+    ValueMap,
+};
 use color_eyre::Report;
-use csv::ByteRecord;
-use std::fs::File;
-use std::io;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
+use csv::QuoteStyle;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::Duration;
 use structopt::{clap::AppSettings::ColoredHelp, StructOpt};
-use tracing::info;
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-#[derive(Debug)]
-enum CleanseChanges {
-    DelimiterReplacement,
-    TerminatorReplacement,
-    FixedEncoding,
-}
-
-#[inline]
-fn cleanse_field(bytes: &[u8], delim: u8, record_number: usize, field_number: usize) -> String {
-    // Replace any delimiter or terminator characters
-    let mut changes = vec![];
-    let delim_fixed = bytes.replace((delim as char).to_string(), " ");
-    if delim_fixed != bytes {
-        changes.push(CleanseChanges::DelimiterReplacement);
-    }
-    let term_fixed = delim_fixed.replace("\n", " ");
-    if term_fixed != delim_fixed {
-        changes.push(CleanseChanges::TerminatorReplacement);
-    }
-    // Fix encoding
-    let str = match term_fixed.into_string() {
-        Ok(new_string) => new_string,
-        Err(e @ bstr::FromUtf8Error { .. }) => {
-            changes.push(CleanseChanges::FixedEncoding);
-            e.into_vec().into_string_lossy()
-        }
-    };
-    if !changes.is_empty() {
-        info!(
-            "Record number {}, field number {}: {:?}",
-            record_number, field_number, changes
-        );
-    }
-    str
-}
-
-fn get_input(path: Option<PathBuf>) -> Result<Box<dyn Read>, Report> {
-    let reader: Box<dyn Read> = match path {
-        Some(path) => {
-            if path.as_os_str() == "-" {
-                Box::new(BufReader::new(io::stdin()))
-            } else {
-                Box::new(BufReader::new(File::open(path)?))
-            }
-        }
-        None => Box::new(BufReader::new(io::stdin())),
-    };
-    Ok(reader)
-}
-
-fn get_output(path: Option<PathBuf>) -> Result<Box<dyn Write>, Report> {
-    let writer: Box<dyn Write> = match path {
-        Some(path) => {
-            if path.as_os_str() == "-" {
-                Box::new(BufWriter::new(io::stdout()))
-            } else {
-                Box::new(BufWriter::new(File::create(path)?))
-            }
-        }
-        None => Box::new(BufWriter::new(io::stdout())),
-    };
-    Ok(writer)
-}
-
-/// Check if err is a broken pipe.
-#[inline]
-fn is_broken_pipe(err: &Report) -> bool {
-    if let Some(io_err) = err.root_cause().downcast_ref::<io::Error>() {
-        if io_err.kind() == io::ErrorKind::BrokenPipe {
-            return true;
-        }
-    }
-    false
-}
-
 /// A small program to do clean up delimited data.
 ///
 /// For each field in each record this will do the following:
 ///
 /// 1. Remove the delimiter from inside any quoted fields
-/// 2. Remove the terminator from inside any quoted fields
+/// 2. Remove the terminator (and any `\r`) from inside any quoted fields
 /// 3. Fix any non-UTF8 encodings
 #[derive(StructOpt, Debug)]
 #[structopt(name = "cleanse", author, global_setting(ColoredHelp))]
 struct Opts {
-    /// Delimiter to use for parsing the file, must be a single byte.
-    #[structopt(short, long, default_value = "\t")]
-    delimiter: String,
+    /// Delimiter to use for parsing the file, must be a single byte. Defaults to a tab, or to the
+    /// delimiter of --format. Takes precedence over --format if both are given
+    #[structopt(short, long)]
+    delimiter: Option<String>,
+
+    /// Shorthand for the delimiter of a common format: "csv" for ",", "tsv" for a tab, "psv" for
+    /// "|", or "ssv" for ";"
+    #[structopt(long)]
+    format: Option<DelimitedFormat>,
+
+    /// Pick the delimiter from the first line of each input, out of ",", "\t", "|", ";", and ":",
+    /// using --delimiter if none of them appear
+    #[structopt(long)]
+    auto_detect: bool,
+
+    /// Read the input as fixed width fields of these comma separated widths in bytes, such as
+    /// "10,20,15", instead of as delimited records. Trailing spaces are trimmed from each field
+    #[structopt(long, conflicts_with = "auto-detect")]
+    fixed_width: Option<FixedWidths>,
+
+    /// Print each changed field to stderr as `[record N, field F] "original" -> "cleaned"`, while
+    /// still writing the output as normal
+    #[structopt(long)]
+    diff: bool,
+
+    /// Keep waiting for more input once the end of FILE is reached, like `tail -f`, until
+    /// interrupted. Each record is flushed to the output as soon as it is written. Requires a
+    /// single FILE path
+    #[structopt(long, conflicts_with = "directory")]
+    watch: bool,
+
+    /// How often to check for more input with --watch, in milliseconds
+    #[structopt(long, default_value = "100")]
+    watch_interval: u64,
+
+    /// Delimiter to use for writing the output, must be a single byte. Defaults to `--delimiter`.
+    #[structopt(long)]
+    output_delimiter: Option<String>,
+
+    /// Text to replace any delimiters or terminators found inside of fields with.
+    #[structopt(short, long, default_value = " ")]
+    replacement: String,
+
+    /// Text to replace delimiters inside of fields with, defaults to --replacement
+    #[structopt(long)]
+    delimiter_replacement: Option<String>,
+
+    /// Text to replace "\n", "\r\n", and "\r" inside of fields with, defaults to --replacement
+    #[structopt(long)]
+    terminator_replacement: Option<String>,
+
+    /// Scan the input and exit with code 1 if any field would be changed, writing no output
+    #[structopt(long)]
+    validate: bool,
+
+    /// Treat the first row as a header and write it through unmodified
+    #[structopt(long)]
+    has_headers: bool,
+
+    /// Rename a header column, as "old_name:new_name". May be given multiple times
+    #[structopt(long, number_of_values = 1)]
+    rename: Vec<ColumnRename>,
+
+    /// Convert every header name to lowercase
+    #[structopt(long, conflicts_with = "uppercase-headers")]
+    lowercase_headers: bool,
+
+    /// Convert every header name to uppercase
+    #[structopt(long)]
+    uppercase_headers: bool,
+
+    /// Number of records to cleanse in parallel at a time
+    #[structopt(long, default_value = "1024")]
+    batch_size: usize,
+
+    /// Number of threads to cleanse records with, defaults to the number of available cores
+    #[structopt(short, long)]
+    threads: Option<usize>,
+
+    /// Show a progress indicator on stderr
+    #[structopt(long)]
+    progress: bool,
+
+    /// Encoding of the input, one of utf8, latin1, utf16le, utf16be, or detect. Non UTF-8 input is
+    /// transcoded to UTF-8, and input starting with a UTF-16 BOM is always read as UTF-16. latin1
+    /// (or windows-1252) input is decoded as windows-1252, which most Latin-1 files really are, so
+    /// bytes 0x80 to 0x9F become characters such as € and curly quotes rather than the control
+    /// characters of strict ISO-8859-1
+    #[structopt(long, default_value = "utf8")]
+    input_encoding: InputEncoding,
+
+    /// Guess the encoding of each input from its first 4096 bytes, the same as
+    /// --input-encoding detect. This is a crude heuristic rather than a real encoding detector:
+    /// input is read as UTF-8 if valid, as UTF-16 if many of its byte pairs have a zero in the
+    /// same position, and otherwise as latin1, i.e. windows-1252. Set --input-encoding when the
+    /// encoding is known
+    #[structopt(long, conflicts_with = "input-encoding")]
+    detect_encoding: bool,
+
+    /// Regex substitution to apply to each field after all other fixes, as "pattern=replacement".
+    /// May be given multiple times, substitutions are applied in order
+    #[structopt(long, number_of_values = 1)]
+    regex_replace: Vec<RegexReplace>,
+
+    /// When to quote output fields, one of always, necessary, non-numeric, or never
+    #[structopt(long, default_value = "necessary", parse(try_from_str = parse_quote_style))]
+    quote_style: QuoteStyle,
+
+    /// Quote every output field, the same as --quote-style always
+    #[structopt(long, conflicts_with = "quote-style")]
+    force_quote: bool,
+
+    /// Format to write records in, one of csv, ndjson, json-array, markdown, or table. The JSON
+    /// formats key each record by the header when --has-headers is set, otherwise by field index,
+    /// and markdown likewise heads the table with the header or the field indices. Table draws the
+    /// records in a box for reading in a terminal
+    #[structopt(long, default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// Strip leading and trailing ASCII whitespace from each field
+    #[structopt(long)]
+    trim: bool,
+
+    /// Drop any record with more than N changes across all of its fields
+    #[structopt(long, value_name = "N")]
+    max_errors_per_record: Option<usize>,
+
+    /// Abort before writing anything if more than this fraction of the fields in the first 1000
+    /// records would be changed, i.e. 0.1, as the input is likely not in the expected format
+    #[structopt(long, value_name = "RATIO")]
+    max_change_ratio: Option<f64>,
+
+    /// Quote character to use for parsing and writing, must be a single byte.
+    #[structopt(short, long, default_value = "\"")]
+    quote_char: String,
+
+    /// Comma separated 1-based indices or ranges of the only columns to cleanse, i.e. "2,4" or
+    /// "1-10,20,30-" where "30-" is column 30 to the end. All other fields are written unmodified
+    #[structopt(long, parse(try_from_str = parse_columns))]
+    columns: Option<Columns>,
+
+    /// Comma separated 1-based indices or ranges of columns to leave out of the output, in the
+    /// same format as --columns
+    #[structopt(long, parse(try_from_str = parse_columns))]
+    drop_columns: Option<Columns>,
+
+    /// Comma separated 1-based indices giving the order to write the columns in, i.e. "3,1,2".
+    /// Every column remaining after --drop-columns and --split-field must be listed exactly once
+    #[structopt(long)]
+    reorder_columns: Option<ColumnOrder>,
+
+    /// Split a column in two on the first occurrence of a separator, as
+    /// "column:separator:first_name:second_name" with a 1-based column, i.e.
+    /// "3:,:last_name:first_name". The names are the headers of the new columns
+    #[structopt(long)]
+    split_field: Option<SplitField>,
+
+    /// Truncate any field longer than N bytes, without splitting a multi-byte character
+    #[structopt(long, value_name = "N")]
+    max_field_length: Option<usize>,
+
+    /// End fields truncated by --max-field-length with "...", keeping them within the maximum
+    #[structopt(long, requires = "max-field-length")]
+    truncate_ellipsis: bool,
+
+    /// Replace any ASCII control characters (0x01-0x1F and 0x7F) inside of fields
+    #[structopt(long)]
+    strip_control: bool,
+
+    /// Replace control characters with a "\xHH" escape of their value instead of --replacement,
+    /// implies --strip-control
+    #[structopt(long)]
+    hex_escape_control: bool,
+
+    /// Warn about any record that doesn't have exactly this many fields
+    #[structopt(long)]
+    expected_fields: Option<usize>,
+
+    /// Warn about any record with fewer than this many fields
+    #[structopt(long)]
+    min_fields: Option<usize>,
+
+    /// Warn about any record with more than this many fields
+    #[structopt(long)]
+    max_fields: Option<usize>,
+
+    /// Abort on the first record without the number of fields given by --expected-fields, or
+    /// outside of --min-fields and --max-fields, on duplicate header names, and on empty
+    /// --required-fields
+    #[structopt(long)]
+    strict_fields: bool,
+
+    /// Skip records that can't be parsed, such as those with the wrong number of fields, rather
+    /// than aborting
+    #[structopt(long)]
+    skip_malformed: bool,
+
+    /// Split records that can't be parsed on the delimiter, ignoring quotes, and cleanse them
+    /// rather than aborting
+    #[structopt(long, conflicts_with = "skip-malformed")]
+    raw_split_malformed: bool,
+
+    /// Drop the empty last field of records that end with the delimiter
+    #[structopt(long)]
+    strip_trailing_delimiter: bool,
+
+    /// Number of raw lines to discard from the start of each input before parsing it
+    #[structopt(long, default_value = "0")]
+    skip_lines: usize,
+
+    /// Text to replace empty fields with, such as NULL. Empty fields are left as is by default
+    #[structopt(long)]
+    empty_replacement: Option<String>,
+
+    /// Rewrite numbers like "$1,234.56" or "1 234,56" as plain decimals like "1234.56"
+    #[structopt(long)]
+    normalize_numbers: bool,
+
+    /// Read and write records terminated by "\0" instead of a newline, leaving any newlines inside
+    /// of fields as is
+    #[structopt(long)]
+    nul_terminated: bool,
+
+    /// Line ending to write after each record, "crlf" or "lf". Defaults to "lf", or to "\0" with
+    /// --nul-terminated
+    #[structopt(long)]
+    output_line_ending: Option<LineEnding>,
+
+    /// Replace each run of whitespace inside of a field with a single space, after any --trim
+    #[structopt(long)]
+    collapse_whitespace: bool,
+
+    /// Stop after this many records, without reading the rest of the input. Counts across all of
+    /// the FILEs, or within each file of --directory
+    #[structopt(long)]
+    max_records: Option<usize>,
+
+    /// Number of records to discard before cleansing any, not counting the header. Counts across
+    /// all of the FILEs, or within each file of --directory
+    #[structopt(long, default_value = "0")]
+    skip_records: usize,
+
+    /// Drop any record identical to the one read immediately before it
+    #[structopt(long)]
+    dedup_adjacent: bool,
+
+    /// Comma separated 1-based indices or ranges of key columns, in the same format as --columns.
+    /// Records with the same key as an earlier record are warned about. Every key is kept in
+    /// memory, so this is only suitable for input where they fit
+    #[structopt(long, parse(try_from_str = parse_columns))]
+    unique_key: Option<Columns>,
+
+    /// Drop records with the same --unique-key as an earlier record
+    #[structopt(long, requires = "unique-key")]
+    drop_duplicates: bool,
+
+    /// Warn once more than N distinct --unique-key keys are kept in memory
+    #[structopt(long, value_name = "N", default_value = "10000000")]
+    unique_key_warn_threshold: usize,
+
+    /// Drop any record where every field is empty, such as ",," from a spreadsheet export
+    #[structopt(long)]
+    remove_empty_records: bool,
+
+    /// Fail on the first field that isn't valid UTF-8, instead of replacing the invalid bytes
+    #[structopt(long)]
+    no_encoding_fix: bool,
+
+    /// Replace any field that isn't valid UTF-8 with the base64 encoding of its original bytes,
+    /// rather than replacing just the invalid bytes
+    #[structopt(long, conflicts_with = "no-encoding-fix")]
+    base64_non_utf8: bool,
+
+    /// Replace each byte that isn't part of valid UTF-8 with a "\xHH" escape of its value, rather
+    /// than with the replacement character
+    #[structopt(long, conflicts_with_all = &["no-encoding-fix", "base64-non-utf8"])]
+    hex_non_utf8: bool,
+
+    /// Leave delimiters inside of fields as is, quoting the fields on output instead
+    #[structopt(long)]
+    no_delimiter_fix: bool,
+
+    /// Leave newlines and carriage returns inside of fields as is, quoting the fields on output
+    /// instead
+    #[structopt(long)]
+    no_terminator_fix: bool,
+
+    /// Warn about any field in a column not matching a regex, as "column:pattern" with a 1-based
+    /// column, i.e. "3:^\d{4}-\d{2}-\d{2}$". Checked after all other fixes, without changing the
+    /// field. May be given multiple times
+    #[structopt(long, number_of_values = 1)]
+    allow_pattern: Vec<AllowPattern>,
+
+    /// Comma separated 1-based indices or ranges of columns that must not be empty, in the same
+    /// format as --columns. Empty fields in them are warned about, or abort with --strict-fields
+    #[structopt(long, parse(try_from_str = parse_columns))]
+    required_fields: Option<Columns>,
+
+    /// Replace fields in a column that are exactly a value, as "column:old=new" with a 1-based
+    /// column, i.e. "2:N/A=", including in columns left out of --columns. May be given multiple
+    /// times
+    #[structopt(long, number_of_values = 1)]
+    map_value: Vec<ValueMap>,
+
+    /// Normalize every field to a Unicode normalization form after all other fixes, only "nfc"
+    /// is supported
+    #[structopt(long)]
+    normalize_unicode: Option<UnicodeNormalization>,
+
+    /// Add a leading column with the 1-based number of each record, counting across all FILEs
+    #[structopt(long)]
+    add_record_number: bool,
+
+    /// Header of the column added by --add-record-number, when --has-headers is set
+    #[structopt(long, default_value = "_record_number")]
+    record_number_header: String,
+
+    /// Add a trailing "_source_file" column with the name of the file each record was read from.
+    /// Requires multiple FILEs or --directory
+    #[structopt(long)]
+    add_source_filename: bool,
+
+    /// Use the full path of the file for --add-source-filename, rather than just its name
+    #[structopt(long, requires = "add-source-filename")]
+    full_path: bool,
+
+    /// Path to write a TSV log of every changed field to, "-" to write to stdout
+    #[structopt(long, parse(from_os_str))]
+    change_log: Option<PathBuf>,
+
+    /// Path to also write every changed record to, and every record failing --allow-pattern or
+    /// --required-fields, with the same delimiter and quoting as the output, "-" to write to
+    /// stdout
+    #[structopt(long, parse(from_os_str))]
+    error_output: Option<PathBuf>,
+
+    /// Path to write a JSON summary of the run to once it completes, "-" to write to stdout
+    #[structopt(long, parse(from_os_str))]
+    summary_file: Option<PathBuf>,
+
+    /// Print a TSV table of the number of non-empty fields and the longest field in bytes of each
+    /// column of the output to stderr once the run completes
+    #[structopt(long)]
+    statistics: bool,
+
+    /// Print each kind of change that was made to stderr once the run completes, most frequent
+    /// first, with a bar chart of how often each was made
+    #[structopt(long)]
+    change_histogram: bool,
+
+    /// Exit with code 1 if any field was changed, after writing all output
+    #[structopt(long)]
+    strict: bool,
+
+    /// Compression of the input and output, one of gzip, zstd, bzip2, or none. Defaults to gzip
+    /// for paths ending in ".gz", zstd for ".zst", bzip2 for ".bz2", and to none for other paths
+    /// and for stdin and stdout. Only gzip and none are supported yet, the others are an error
+    /// rather than being read or written uncompressed
+    #[structopt(long)]
+    compression: Option<Compression>,
+
+    /// Level to compress the output at, from 0 (none) to 9 (best) for gzip. Defaults to 6
+    #[structopt(long)]
+    compression_level: Option<u8>,
+
+    /// Memory map each input FILE rather than reading it, which can be faster for very large
+    /// files. Only works for regular files, not stdin
+    #[structopt(long)]
+    mmap: bool,
+
+    /// Size of the buffer to read each FILE through, as a number of bytes optionally followed by
+    /// KB, MB, or GB, such as "1MB". Defaults to 8KB
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    read_buffer_size: Option<usize>,
+
+    /// Size of the buffer to write the output through, in the same form as --read-buffer-size.
+    /// Defaults to 8KB
+    #[structopt(long, parse(try_from_str = parse_byte_size))]
+    write_buffer_size: Option<usize>,
+
+    /// Only count the records, printing the total number of records read to stdout
+    #[structopt(long)]
+    count: bool,
+
+    /// TOML file to read options from, keyed by their long names. Options given on the command
+    /// line take precedence, and `files = [...]` is only used if no FILE is given
+    #[structopt(long, parse(from_os_str))]
+    #[allow(dead_code)] // Only read from the raw matches, by `parse_args`
+    config: Option<PathBuf>,
 
     /// Output path to write to, "-" to write to stdout
     #[structopt(short, long)]
     output: Option<PathBuf>,
 
-    /// Input file to read from, "-" to read from stdin
+    /// Cleanse each file in this directory matching --glob into its own output file in
+    /// --output-directory, instead of reading the FILEs. A file that fails is logged and skipped
+    /// unless --strict is set
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["FILE", "output"])]
+    directory: Option<PathBuf>,
+
+    /// Pattern the names of the files to cleanse in --directory must match
+    #[structopt(long, default_value = "*.csv")]
+    glob: String,
+
+    /// Directory to write the cleansed files from --directory to, defaults to a "cleansed"
+    /// directory inside of --directory
+    #[structopt(long, parse(from_os_str), requires = "directory")]
+    output_directory: Option<PathBuf>,
+
+    /// Write every N records to a new file named after --output with a chunk number before the
+    /// extension, i.e. "out_000.csv", "out_001.csv", and so on. Each file gets its own header
+    #[structopt(long, value_name = "N", conflicts_with = "directory")]
+    split_records: Option<usize>,
+
+    /// Input files to read from in order, "-" to read from stdin
     #[structopt(name = "FILE", parse(from_os_str))]
-    file: Option<PathBuf>,
+    files: Vec<PathBuf>,
 }
 
 fn main() -> Result<(), Report> {
     let opts = setup()?;
-    if opts.delimiter.as_bytes().len() != 1 {
-        return Err(Report::msg("Input delimiter may only be a single byte"));
-    }
+    let fail_on_changes = opts.validate || opts.strict;
+    let count = opts.count;
+    let summary_file = opts.summary_file.clone();
+    let statistics = opts.statistics;
+    let change_histogram = opts.change_histogram;
 
-    if let Err(err) = run(
-        get_input(opts.file)?,
-        get_output(opts.output)?,
-        opts.delimiter.as_bytes()[0],
-    ) {
-        if is_broken_pipe(&err) {
-            exit(0)
+    match cleanse(opts) {
+        Ok(stats) => {
+            if count {
+                println!("{}", stats.records);
+            }
+            if let Some(path) = summary_file {
+                write_summary(path, &stats)?;
+            }
+            if statistics {
+                stats.write_column_stats(io::stderr().lock())?;
+            }
+            if change_histogram {
+                stats.write_change_histogram(io::stderr().lock())?;
+            }
+            if fail_on_changes && stats.has_changes() {
+                exit(1)
+            }
+        }
+        Err(err) => {
+            if is_broken_pipe(&err) {
+                exit(0)
+            }
+            return Err(err);
         }
-        return Err(err);
     }
     Ok(())
 }
 
-/// Run the program, returning any found errors
-fn run<R, W>(input: R, output: W, delimiter: u8) -> Result<(), Report>
-where
-    R: Read,
-    W: Write,
-{
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .delimiter(delimiter)
-        .from_reader(input);
+/// Cleanse each of the input files into the single output
+fn cleanse(mut opts: Opts) -> Result<RunStats, Report> {
+    if opts.detect_encoding {
+        opts.input_encoding = InputEncoding::Detect;
+    }
+    let delimiter = match (&opts.delimiter, opts.format) {
+        (Some(delimiter), format) => {
+            if let Some(format) = format {
+                warn!(
+                    "Both --delimiter {:?} and --format {:?} given, using --delimiter",
+                    delimiter, format
+                );
+            }
+            single_byte(delimiter, "Input delimiter")?
+        }
+        (None, Some(format)) => format.delimiter(),
+        (None, None) => b'\t',
+    };
+    let output_delimiter = match opts.output_delimiter {
+        Some(output_delimiter) => single_byte(&output_delimiter, "Output delimiter")?,
+        None => delimiter,
+    };
+    let quote = single_byte(&opts.quote_char, "Quote character")?;
+    if quote == delimiter || quote == output_delimiter {
+        return Err(CleanseError::InvalidDelimiter(
+            "Quote character may not be the same as the delimiter".to_string(),
+        )
+        .into());
+    }
 
-    let mut writer = csv::WriterBuilder::new()
-        .has_headers(false)
+    if opts.strict_fields
+        && opts.expected_fields.is_none()
+        && opts.min_fields.is_none()
+        && opts.max_fields.is_none()
+        && opts.required_fields.is_none()
+        && !opts.has_headers
+    {
+        return Err(CleanseError::InvalidOptions(
+            "--strict-fields requires --has-headers, --expected-fields, --min-fields, \
+             --max-fields, or --required-fields"
+                .to_string(),
+        )
+        .into());
+    }
+    if let (Some(min), Some(max)) = (opts.min_fields, opts.max_fields) {
+        if min > max {
+            return Err(CleanseError::InvalidOptions(
+                "--min-fields may not be greater than --max-fields".to_string(),
+            )
+            .into());
+        }
+    }
+
+    if opts.split_records.is_some() {
+        if opts
+            .output
+            .as_ref()
+            .is_none_or(|path| path.as_os_str() == "-")
+        {
+            return Err(CleanseError::InvalidOptions(
+                "--split-records requires an --output path to name the files after".to_string(),
+            )
+            .into());
+        }
+        if opts.files.len() > 1 {
+            return Err(CleanseError::InvalidOptions(
+                "--split-records may only be used with a single FILE".to_string(),
+            )
+            .into());
+        }
+    }
+
+    if opts.compression_level.is_some_and(|level| level > 9) {
+        return Err(CleanseError::InvalidOptions(
+            "--compression-level must be from 0 to 9".to_string(),
+        )
+        .into());
+    }
+
+    if opts
+        .max_change_ratio
+        .is_some_and(|ratio| !(0.0..=1.0).contains(&ratio))
+    {
+        return Err(CleanseError::InvalidOptions(
+            "--max-change-ratio must be from 0 to 1".to_string(),
+        )
+        .into());
+    }
+
+    if opts.watch && (opts.files.len() != 1 || opts.files[0].as_os_str() == "-") {
+        return Err(CleanseError::InvalidOptions(
+            "--watch requires a single FILE path to read from".to_string(),
+        )
+        .into());
+    }
+
+    if opts.add_source_filename && opts.directory.is_none() && opts.files.len() < 2 {
+        return Err(CleanseError::InvalidOptions(
+            "--add-source-filename requires multiple FILEs or --directory".to_string(),
+        )
+        .into());
+    }
+
+    let defaults = CleanseOptions::default();
+    let threads = opts.threads.unwrap_or(defaults.threads);
+    let mut cleanse_opts = defaults
         .delimiter(delimiter)
-        .from_writer(output);
+        .output_delimiter(output_delimiter)
+        .replacement(opts.replacement)
+        .delimiter_replacement(opts.delimiter_replacement)
+        .terminator_replacement(opts.terminator_replacement)
+        .dry_run(opts.validate || opts.count)
+        .has_headers(opts.has_headers)
+        .renames(opts.rename)
+        .header_case(if opts.lowercase_headers {
+            Some(HeaderCase::Lower)
+        } else if opts.uppercase_headers {
+            Some(HeaderCase::Upper)
+        } else {
+            None
+        })
+        .batch_size(opts.batch_size)
+        .threads(threads)
+        .progress(opts.progress)
+        .regex_replacements(opts.regex_replace)
+        .quote_style(if opts.force_quote {
+            QuoteStyle::Always
+        } else {
+            opts.quote_style
+        })
+        .output_format(opts.output_format)
+        .trim(opts.trim)
+        .max_errors_per_record(opts.max_errors_per_record)
+        .max_change_ratio(opts.max_change_ratio)
+        .quote(quote)
+        .columns(opts.columns)
+        .drop_columns(opts.drop_columns)
+        .reorder_columns(opts.reorder_columns)
+        .split_field(opts.split_field)
+        .max_field_length(opts.max_field_length)
+        .truncate_ellipsis(opts.truncate_ellipsis)
+        .strip_control(opts.strip_control)
+        .hex_escape_control(opts.hex_escape_control)
+        .expected_fields(opts.expected_fields)
+        .min_fields(opts.min_fields)
+        .max_fields(opts.max_fields)
+        .strict_fields(opts.strict_fields)
+        .skip_malformed(opts.skip_malformed)
+        .raw_split_malformed(opts.raw_split_malformed)
+        .strip_trailing_delimiter(opts.strip_trailing_delimiter)
+        .skip_lines(opts.skip_lines)
+        .empty_replacement(opts.empty_replacement)
+        .normalize_numbers(opts.normalize_numbers)
+        .nul_terminated(opts.nul_terminated)
+        .output_line_ending(opts.output_line_ending)
+        .collapse_whitespace(opts.collapse_whitespace)
+        .max_records(opts.max_records)
+        .skip_records(opts.skip_records)
+        .auto_detect(opts.auto_detect)
+        .dedup_adjacent(opts.dedup_adjacent)
+        .unique_key(opts.unique_key)
+        .drop_duplicates(opts.drop_duplicates)
+        .unique_key_warn_threshold(opts.unique_key_warn_threshold)
+        .remove_empty_records(opts.remove_empty_records)
+        .no_encoding_fix(opts.no_encoding_fix)
+        .no_delimiter_fix(opts.no_delimiter_fix)
+        .no_terminator_fix(opts.no_terminator_fix)
+        .allow_patterns(opts.allow_pattern)
+        .required_fields(opts.required_fields)
+        .value_maps(opts.map_value)
+        .normalize_unicode(opts.normalize_unicode)
+        .base64_non_utf8(opts.base64_non_utf8)
+        .hex_non_utf8(opts.hex_non_utf8)
+        .split_records(opts.split_records)
+        .add_record_number(opts.add_record_number)
+        .record_number_header(opts.record_number_header)
+        .fixed_width(opts.fixed_width)
+        .diff(opts.diff)
+        .statistics(opts.statistics)
+        .watch_interval(if opts.watch {
+            Some(Duration::from_millis(opts.watch_interval))
+        } else {
+            None
+        });
 
-    let mut record_number = 0;
-    let mut reader_record = ByteRecord::new();
-    let mut writer_record = ByteRecord::new();
+    let write_output = !(opts.validate || opts.count);
+    let mut side_outputs = SideOutputs {
+        change_log: match opts.change_log {
+            Some(path) => Some(ChangeLog::new(Box::new(get_output(
+                Some(path),
+                Some(Compression::None),
+                None,
+                None,
+            )?))?),
+            None => None,
+        },
+        error_output: match opts.error_output {
+            Some(path) => Some(ErrorOutput::new(Box::new(get_output(
+                Some(path),
+                Some(Compression::None),
+                None,
+                None,
+            )?))),
+            None => None,
+        },
+    };
 
-    while let Ok(is_more) = reader.read_byte_record(&mut reader_record) {
-        if !is_more {
-            break;
+    let files_opts = FileOptions {
+        encoding: opts.input_encoding,
+        compression: opts.compression,
+        compression_level: opts.compression_level,
+        mmap: opts.mmap,
+        read_buffer_size: opts.read_buffer_size,
+        write_buffer_size: opts.write_buffer_size,
+    };
+
+    if let Some(directory) = opts.directory {
+        let output_directory = opts
+            .output_directory
+            .unwrap_or_else(|| directory.join("cleansed"));
+        let files = find_files(&directory, &opts.glob)?;
+        if write_output {
+            fs::create_dir_all(&output_directory)?;
+            // Each output would truncate its input before it was read
+            if fs::canonicalize(&output_directory)? == fs::canonicalize(&directory)? {
+                return Err(CleanseError::InvalidOptions(
+                    "--output-directory may not be the same as --directory".to_string(),
+                )
+                .into());
+            }
         }
-        reader_record
-            .into_iter()
-            .enumerate()
-            .for_each(|(field_number, field)| {
-                let field = cleanse_field(field, delimiter, record_number, field_number);
-                writer_record.push_field(field.as_bytes());
+        let mut stats = RunStats::default();
+        let mut failures = 0;
+        for (i, input) in files.iter().enumerate() {
+            info!("Cleansing file {} of {}: {:?}", i + 1, files.len(), input);
+            if opts.add_source_filename {
+                cleanse_opts.source_filename = Some(source_filename(input, opts.full_path));
+            }
+            let output_path =
+                output_directory.join(input.file_name().expect("Found files have names"));
+            let result = if write_output {
+                files_opts.output(Some(output_path))
+            } else {
+                Ok(Output::sink())
+            }
+            .and_then(|output| {
+                let mut output = SharedOutput::new(output);
+                let file_stats = cleanse_input(
+                    input,
+                    &mut output,
+                    &mut side_outputs,
+                    &mut cleanse_opts,
+                    &files_opts,
+                )?;
+                output.finish(&cleanse_opts)?.finish()?;
+                Ok(file_stats)
             });
+            match result {
+                Ok(file_stats) => stats.merge(&file_stats),
+                Err(err) if opts.strict => return Err(err.into()),
+                Err(err) => {
+                    error!("Failed to cleanse {:?}: {}", input, err);
+                    failures += 1;
+                }
+            }
+        }
+        if failures > 0 {
+            return Err(Report::msg(format!(
+                "Failed to cleanse {} of {} files",
+                failures,
+                files.len()
+            )));
+        }
+        return Ok(stats);
+    }
 
-        writer.write_byte_record(&writer_record)?;
-        reader_record.clear();
-        writer_record.clear();
-        record_number += 1;
+    if let (Some(_), Some(path)) = (opts.split_records, &opts.output) {
+        let input = opts
+            .files
+            .first()
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from("-"));
+        cleanse_opts.input_size = input_size(&input);
+        return Ok(run_split(
+            files_opts.input(input)?,
+            |chunk| {
+                if write_output {
+                    files_opts.output(Some(chunk_path(path, chunk)))
+                } else {
+                    Ok(Output::sink())
+                }
+            },
+            Output::finish,
+            &mut side_outputs,
+            &cleanse_opts,
+        )?);
     }
 
-    Ok(())
+    let mut output = SharedOutput::new(if write_output {
+        files_opts.output(opts.output)?
+    } else {
+        Output::sink()
+    });
+
+    let inputs = if opts.files.is_empty() {
+        vec![PathBuf::from("-")]
+    } else {
+        opts.files
+    };
+
+    let mut stats = RunStats::default();
+    for (i, input) in inputs.into_iter().enumerate() {
+        // Everything goes to the one output, which only needs the first input's header
+        cleanse_opts.skip_header = i > 0;
+        if let Some(max_records) = opts.max_records {
+            if stats.records as usize >= max_records {
+                break;
+            }
+            cleanse_opts.max_records = Some(max_records - stats.records as usize);
+        }
+        cleanse_opts.skip_records = opts
+            .skip_records
+            .saturating_sub(stats.records_skipped as usize);
+        cleanse_opts.first_record_number =
+            (stats.records + stats.malformed_records + stats.records_skipped) as usize;
+        if opts.add_source_filename {
+            cleanse_opts.source_filename = Some(source_filename(&input, opts.full_path));
+        }
+        stats.merge(&cleanse_input(
+            &input,
+            &mut output,
+            &mut side_outputs,
+            &mut cleanse_opts,
+            &files_opts,
+        )?);
+    }
+    output.finish(&cleanse_opts)?.finish()?;
+    Ok(stats)
+}
+
+/// How to open each input and output file
+#[derive(Debug, Clone, Copy)]
+struct FileOptions {
+    encoding: InputEncoding,
+    compression: Option<Compression>,
+    compression_level: Option<u8>,
+    mmap: bool,
+    read_buffer_size: Option<usize>,
+    write_buffer_size: Option<usize>,
+}
+
+impl FileOptions {
+    fn input(&self, path: PathBuf) -> Result<Box<dyn Read>, CleanseError> {
+        get_input(
+            Some(path),
+            self.encoding,
+            self.compression,
+            self.mmap,
+            self.read_buffer_size,
+        )
+    }
+
+    fn output(&self, path: Option<PathBuf>) -> Result<Output, CleanseError> {
+        get_output(
+            path,
+            self.compression,
+            self.compression_level,
+            self.write_buffer_size,
+        )
+    }
+}
+
+/// Cleanse a single input, "-" for stdin, into `output`
+fn cleanse_input(
+    input: &Path,
+    output: &mut SharedOutput<Output>,
+    side_outputs: &mut SideOutputs,
+    cleanse_opts: &mut CleanseOptions,
+    files_opts: &FileOptions,
+) -> Result<RunStats, CleanseError> {
+    cleanse_opts.input_size = input_size(input);
+    run_shared(
+        files_opts.input(input.to_path_buf())?,
+        output,
+        side_outputs,
+        cleanse_opts,
+    )
+}
+
+/// The name of `input` to add to its records, or its whole path if `full_path` is set
+fn source_filename(input: &Path, full_path: bool) -> String {
+    match input.file_name() {
+        Some(name) if !full_path => name.to_string_lossy().into_owned(),
+        _ => input.to_string_lossy().into_owned(),
+    }
+}
+
+/// The size of the regular file at `input` if it is one, for showing progress
+fn input_size(input: &Path) -> Option<u64> {
+    Some(input)
+        .filter(|path| path.as_os_str() != "-")
+        .and_then(|path| fs::metadata(path).ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+}
+
+/// Write the pretty printed JSON summary of `stats` to `path`
+fn write_summary(path: PathBuf, stats: &RunStats) -> Result<(), CleanseError> {
+    let mut output = get_output(Some(path), Some(Compression::None), None, None)?;
+    serde_json::to_writer_pretty(&mut output, &stats.summary())?;
+    writeln!(output)?;
+    output.finish()
+}
+
+/// Check if err is a broken pipe.
+#[inline]
+fn is_broken_pipe(err: &Report) -> bool {
+    match err.downcast_ref::<CleanseError>() {
+        Some(err) => err.is_broken_pipe(),
+        None => err
+            .root_cause()
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe),
+    }
+}
+
+/// Get the single byte `value` is made up of, `name` is used to describe it in any error
+fn single_byte(value: &str, name: &str) -> Result<u8, CleanseError> {
+    if value.len() != 1 {
+        return Err(CleanseError::InvalidDelimiter(format!(
+            "{} may only be a single byte",
+            name
+        )));
+    }
+    Ok(value.as_bytes()[0])
 }
 
 /// Parse args and set up logging / tracing
@@ -183,7 +942,68 @@ fn setup() -> Result<Opts, Report> {
         .with_writer(std::io::stderr)
         .init();
 
-    Ok(Opts::from_args())
+    parse_args(std::env::args_os())
+}
+
+/// Parse `args`, filling in any options not given on the command line from the `--config` file.
+fn parse_args<I: IntoIterator<Item = OsString>>(args: I) -> Result<Opts, Report> {
+    let args: Vec<OsString> = args.into_iter().collect();
+    let matches = Opts::clap().get_matches_from(&args);
+    let path = match matches.value_of_os("config") {
+        Some(path) => PathBuf::from(path),
+        None => return Ok(Opts::from_clap(&matches)),
+    };
+    let config = Config::from_path(&path)?;
+
+    let mut config_args = vec![];
+    let mut config_files = vec![];
+    for (key, value) in &config.entries {
+        let name = match key.as_str() {
+            "config" => {
+                return Err(Report::msg(format!(
+                    "Invalid config file {:?}: config files may not set config",
+                    path
+                )))
+            }
+            "files" => "FILE",
+            key => key,
+        };
+        if matches.occurrences_of(name) > 0 {
+            continue;
+        }
+        let values = match value {
+            ConfigValue::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                ConfigValue::Boolean(true) => {
+                    config_args.push(OsString::from(format!("--{}", key)));
+                    continue;
+                }
+                ConfigValue::Boolean(false) => continue,
+                ConfigValue::String(s) => s.clone(),
+                ConfigValue::Integer(i) => i.to_string(),
+                ConfigValue::Array(_) => unreachable!("Nested arrays are rejected when parsing"),
+            };
+            if name == "FILE" {
+                config_files.push(OsString::from(value));
+            } else {
+                config_args.push(OsString::from(format!("--{}={}", key, value)));
+            }
+        }
+    }
+
+    let merged = args
+        .iter()
+        .take(1)
+        .chain(&config_args)
+        .chain(args.iter().skip(1))
+        .chain(&config_files);
+    let matches = Opts::clap()
+        .get_matches_from_safe(merged)
+        .map_err(|e| Report::msg(format!("Invalid config file {:?}: {}", path, e.message)))?;
+    Ok(Opts::from_clap(&matches))
 }
 
 #[cfg(test)]
@@ -191,23 +1011,104 @@ mod test {
     use super::*;
 
     #[test]
-    fn test_simple() {
-        let input = b"\
-        a,b,c,d\n\
-        1,\"2,3\",4,5\n\
-        this,is,\"a\n\
-        very gross\",li\xffe\n"
-            .to_vec();
-
-        let expected = String::from(
-            "\
-        a,b,c,d\n\
-        1,2 3,4,5\n\
-        this,is,a very gross,li�e\n",
+    fn test_config() {
+        let path = std::env::temp_dir().join(format!("cleanse_config_{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "delimiter = \"\\t\"\nhas_headers = true\nregex_replace = [\"a=b\", \"c=d\"]\n\
+             files = [\"in.tsv\"]\n",
+        )
+        .unwrap();
+        let args = |extra: &[&str]| {
+            let mut args = vec![OsString::from("cleanse"), OsString::from("--config")];
+            args.push(path.clone().into_os_string());
+            args.extend(extra.iter().map(OsString::from));
+            args
+        };
+
+        let opts = parse_args(args(&[])).unwrap();
+        assert_eq!(opts.delimiter.as_deref(), Some("\t"));
+        assert!(opts.has_headers);
+        assert_eq!(opts.regex_replace.len(), 2);
+        assert_eq!(opts.files, vec![PathBuf::from("in.tsv")]);
+
+        let opts = parse_args(args(&["--delimiter", ",", "other.csv"])).unwrap();
+        assert_eq!(opts.delimiter.as_deref(), Some(","));
+        assert_eq!(opts.files, vec![PathBuf::from("other.csv")]);
+
+        fs::write(&path, "not_an_option = 1\n").unwrap();
+        assert!(parse_args(args(&[])).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Cleanse `input` with the command line `args`, returning the error if it fails.
+    fn cleanse_with(name: &str, input: &[u8], args: &[&str]) -> Option<String> {
+        let path =
+            std::env::temp_dir().join(format!("cleanse_{}_{}.csv", name, std::process::id()));
+        fs::write(&path, input).unwrap();
+        let mut all_args = vec![
+            OsString::from("cleanse"),
+            OsString::from("--validate"),
+            path.clone().into_os_string(),
+        ];
+        all_args.extend(args.iter().map(OsString::from));
+        let result = cleanse(parse_args(all_args).unwrap());
+        fs::remove_file(&path).unwrap();
+        result.err().map(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_strict_required_fields() {
+        let args = [
+            "--delimiter",
+            ",",
+            "--required-fields",
+            "1",
+            "--strict-fields",
+        ];
+        assert_eq!(
+            cleanse_with("strict_required", b"a,b\n,c\n", &args).as_deref(),
+            Some("Record number 1, field number 0: required field is empty")
         );
+    }
 
-        let mut writer = vec![];
-        run(input.as_slice(), &mut writer, b',').unwrap();
-        assert_eq!(expected, writer.into_string().unwrap());
+    #[test]
+    fn test_output_directory_is_input_directory() {
+        let directory =
+            std::env::temp_dir().join(format!("cleanse_same_dir_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("a.csv");
+        fs::write(&input, "a,b\n").unwrap();
+        let args = vec![
+            OsString::from("cleanse"),
+            OsString::from("--directory"),
+            directory.clone().into_os_string(),
+            OsString::from("--output-directory"),
+            directory.join(".").into_os_string(),
+        ];
+        let err = cleanse(parse_args(args).unwrap()).unwrap_err();
+        let contents = fs::read_to_string(&input).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            err.to_string(),
+            "--output-directory may not be the same as --directory"
+        );
+        assert_eq!(contents, "a,b\n");
+    }
+
+    #[test]
+    fn test_strict_duplicate_headers() {
+        let args = ["--delimiter", ",", "--has-headers", "--strict-fields"];
+        let input = b"id,name,name,value\n1,a,b,c\n";
+        let err = cleanse_with("strict_duplicate", input, &args).unwrap();
+        assert!(
+            err.starts_with("Duplicate header \"name\" in columns"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            cleanse_with("strict_unique", b"id,name\n1,a\n", &args),
+            None
+        );
     }
 }
@@ -0,0 +1,118 @@
+//! Read only memory mapping of input files, to avoid copying them through a read buffer.
+use crate::error::CleanseError;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+/// A whole file mapped read only into memory.
+///
+/// The file must not be truncated while it is mapped, any access to the truncated part of the
+/// mapping is then a bus error.
+#[derive(Debug)]
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is read only and not tied to the thread that made it
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map the regular file at `path`.
+    #[cfg(unix)]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CleanseError> {
+        use std::os::unix::io::AsRawFd;
+
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(CleanseError::InvalidOptions(format!(
+                "Only regular files can be memory mapped, {:?} is not one",
+                path
+            )));
+        }
+        let len = metadata.len() as usize;
+        // Empty mappings aren't allowed, and there's nothing to map anyway
+        if len == 0 {
+            return Ok(Self {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+        // Safety: the arguments describe a read only private mapping of the whole file, which
+        // stays valid after the file is closed, and is checked for failure before use
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    /// Memory mapping is only supported on Unix.
+    #[cfg(not(unix))]
+    pub fn open<P: AsRef<Path>>(_path: P) -> Result<Self, CleanseError> {
+        Err(CleanseError::InvalidOptions(String::from(
+            "Memory mapping is not supported on this platform",
+        )))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: `ptr` is either a live mapping of `len` bytes or dangling with a `len` of 0
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.len > 0 {
+            // Safety: the mapping was made in `open` with this address and length
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mmap() {
+        let path = std::env::temp_dir().join(format!("cleanse_mmap_{}.csv", std::process::id()));
+        std::fs::write(&path, b"a,b\nc,d\n").unwrap();
+        assert_eq!(&*Mmap::open(&path).unwrap(), b"a,b\nc,d\n");
+
+        std::fs::write(&path, b"").unwrap();
+        assert!(Mmap::open(&path).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Mmap::open(std::env::temp_dir()).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Unicode normalization of fields, so that the same text is always written as the same code
+//! points, whether the source system used precomposed or decomposed characters.
+use crate::normalize_tables::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// The Unicode normalization forms fields may be normalized to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeNormalization {
+    /// Canonical decomposition followed by canonical composition, so that precomposed characters
+    /// such as `é` are used wherever they exist.
+    Nfc,
+}
+
+impl UnicodeNormalization {
+    /// `s` in this normalization form, borrowed if it already is.
+    pub fn apply(self, s: &str) -> Cow<'_, str> {
+        match self {
+            UnicodeNormalization::Nfc => nfc(s),
+        }
+    }
+}
+
+impl FromStr for UnicodeNormalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nfc" => Ok(UnicodeNormalization::Nfc),
+            _ => Err(format!(
+                "Unknown Unicode normalization form {:?}, expected nfc",
+                s
+            )),
+        }
+    }
+}
+
+// Hangul syllables are decomposed and composed arithmetically rather than through the tables
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+/// The canonical combining class of `c`, 0 for starters.
+fn combining_class(c: char) -> u8 {
+    COMBINING_CLASSES
+        .binary_search_by(|&(start, end, _)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map_or(0, |i| COMBINING_CLASSES[i].2)
+}
+
+/// Push the full canonical decomposition of `c` to `decomposed`.
+fn decompose(c: char, decomposed: &mut Vec<char>) {
+    let s = c as u32;
+    if (S_BASE..S_BASE + S_COUNT).contains(&s) {
+        let index = s - S_BASE;
+        let jamo = |c| char::from_u32(c).expect("Hangul jamo are valid characters");
+        decomposed.push(jamo(L_BASE + index / N_COUNT));
+        decomposed.push(jamo(V_BASE + (index % N_COUNT) / T_COUNT));
+        if !index.is_multiple_of(T_COUNT) {
+            decomposed.push(jamo(T_BASE + index % T_COUNT));
+        }
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(c, _, _)| c) {
+        Ok(i) => {
+            let (_, first, second) = DECOMPOSITIONS[i];
+            decompose(first, decomposed);
+            if second != '\0' {
+                decompose(second, decomposed);
+            }
+        }
+        Err(_) => decomposed.push(c),
+    }
+}
+
+/// The primary composite of `first` followed by `second`, if there is one.
+fn compose(first: char, second: char) -> Option<char> {
+    let (a, b) = (first as u32, second as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+        return char::from_u32(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT);
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&a)
+        && (a - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&b)
+    {
+        return char::from_u32(a + b - T_BASE);
+    }
+    COMPOSITIONS
+        .binary_search_by_key(&(first, second), |&(a, b, _)| (a, b))
+        .ok()
+        .map(|i| COMPOSITIONS[i].2)
+}
+
+/// `s` normalized to NFC, borrowed if it already is.
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    // Nothing before the combining diacritical marks is changed by normalization
+    if s.chars().all(|c| c < '\u{300}') {
+        return Cow::Borrowed(s);
+    }
+
+    let mut decomposed = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        decompose(c, &mut decomposed);
+    }
+    // Put each run of combining marks in canonical order, the sort is stable as required
+    let mut start = 0;
+    while start < decomposed.len() {
+        let len = decomposed[start..]
+            .iter()
+            .take_while(|&&c| combining_class(c) != 0)
+            .count();
+        decomposed[start..start + len].sort_by_key(|&c| combining_class(c));
+        start += len.max(1);
+    }
+
+    let mut composed: Vec<char> = Vec::with_capacity(decomposed.len());
+    // The last starter, and the class of the last character after it that wasn't composed with it
+    let mut starter = None;
+    let mut last_class = None;
+    for c in decomposed {
+        let class = combining_class(c);
+        if let Some(starter) = starter {
+            // Blocked by any character since the starter of the same class, or that is a starter
+            let blocked =
+                last_class.is_some_and(|last_class| last_class == 0 || last_class >= class);
+            if let (false, Some(composite)) = (blocked, compose(composed[starter], c)) {
+                composed[starter] = composite;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(composed.len());
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        composed.push(c);
+    }
+
+    if composed.iter().copied().eq(s.chars()) {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(composed.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nfc() {
+        // Decomposed and precomposed forms of the same text
+        assert_eq!(nfc("caf\u{65}\u{301}"), "caf\u{e9}");
+        assert!(matches!(nfc("caf\u{e9}"), Cow::Borrowed(_)));
+        assert!(matches!(nfc("plain ascii"), Cow::Borrowed(_)));
+        // Marks are reordered before composing, and singletons are replaced
+        assert_eq!(nfc("a\u{323}\u{302}"), "\u{1ead}");
+        assert_eq!(nfc("a\u{302}\u{323}"), "\u{1ead}");
+        assert_eq!(nfc("\u{212b}"), "\u{c5}");
+        // A mark of the same class in between blocks composition
+        assert_eq!(nfc("a\u{301}\u{301}"), "\u{e1}\u{301}");
+        // Hangul syllables
+        assert_eq!(nfc("\u{1100}\u{1161}\u{11a8}"), "\u{ac01}");
+        assert_eq!(nfc("\u{ac01}"), "\u{ac01}");
+        // Leading marks are left as is
+        assert_eq!(nfc("\u{301}e"), "\u{301}e");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "NFC".parse::<UnicodeNormalization>().unwrap(),
+            UnicodeNormalization::Nfc
+        );
+        assert!("nfd".parse::<UnicodeNormalization>().is_err());
+    }
+}
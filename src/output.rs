@@ -0,0 +1,402 @@
+//! Writers for each of the supported output formats.
+use crate::error::CleanseError;
+use crate::CleanseOptions;
+use csv::ByteRecord;
+use std::io::{self, Write};
+use std::iter;
+use std::str::FromStr;
+
+/// A builder for the CSV writer [`OutputFormat::Csv`] output is written with.
+pub(crate) fn csv_writer_builder(opts: &CleanseOptions) -> csv::WriterBuilder {
+    let mut builder = csv::WriterBuilder::new();
+    builder
+        .has_headers(opts.has_headers)
+        .delimiter(opts.output_delimiter)
+        .quote_style(opts.quote_style)
+        .quote(opts.quote)
+        .flexible(
+            opts.checks_field_count() || opts.raw_split_malformed || opts.strip_trailing_delimiter,
+        )
+        .terminator(match opts.output_line_ending {
+            Some(LineEnding::Crlf) => csv::Terminator::CRLF,
+            Some(LineEnding::Lf) => csv::Terminator::Any(b'\n'),
+            None if opts.nul_terminated => csv::Terminator::Any(b'\0'),
+            None => csv::Terminator::Any(b'\n'),
+        });
+    builder
+}
+
+/// The formats cleansed records may be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Delimited text, using the output delimiter.
+    Csv,
+    /// One JSON object per record per line, keyed by the header or by field index.
+    Ndjson,
+    /// A single JSON array of objects keyed the same way as [`OutputFormat::Ndjson`].
+    JsonArray,
+    /// A Markdown pipe table, with the header as the first row or the field indices if there is
+    /// no header. All records are kept in memory until the end of the run to line up the columns.
+    Markdown,
+    /// A table drawn with box drawing characters for reading in a terminal, with the header as
+    /// the first row if there is one. All records are kept in memory until the end of the run to
+    /// size the columns.
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "json-array" => Ok(OutputFormat::JsonArray),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(format!(
+                "Unknown output format {:?}, expected one of csv, ndjson, json-array, markdown, \
+                 table",
+                s
+            )),
+        }
+    }
+}
+
+/// The line ending to end each record of delimited output with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\r\n`, as expected by many Windows programs.
+    Crlf,
+    /// `\n`.
+    Lf,
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "crlf" => Ok(LineEnding::Crlf),
+            "lf" => Ok(LineEnding::Lf),
+            _ => Err(format!(
+                "Unknown line ending {:?}, expected one of crlf, lf",
+                s
+            )),
+        }
+    }
+}
+
+/// Writes records in the configured [`OutputFormat`].
+pub enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Ndjson {
+        writer: W,
+        keys: Vec<String>,
+    },
+    JsonArray {
+        writer: W,
+        keys: Vec<String>,
+        first_record: bool,
+    },
+    Markdown {
+        writer: W,
+        header: Option<Vec<String>>,
+        rows: Vec<Vec<String>>,
+    },
+    Table {
+        writer: W,
+        header: Option<Vec<String>>,
+        rows: Vec<Vec<String>>,
+    },
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Create a writer for `opts.output_format`.
+    pub fn new(output: W, opts: &CleanseOptions) -> Self {
+        match opts.output_format {
+            OutputFormat::Csv => {
+                RecordWriter::Csv(Box::new(csv_writer_builder(opts).from_writer(output)))
+            }
+            OutputFormat::Ndjson => RecordWriter::Ndjson {
+                writer: output,
+                keys: vec![],
+            },
+            OutputFormat::JsonArray => RecordWriter::JsonArray {
+                writer: output,
+                keys: vec![],
+                first_record: true,
+            },
+            OutputFormat::Markdown => RecordWriter::Markdown {
+                writer: output,
+                header: None,
+                rows: vec![],
+            },
+            OutputFormat::Table => RecordWriter::Table {
+                writer: output,
+                header: None,
+                rows: vec![],
+            },
+        }
+    }
+
+    /// Write the header record, or for keyed formats, remember it for use as keys.
+    pub fn write_header(&mut self, header: &ByteRecord) -> Result<(), CleanseError> {
+        match self {
+            RecordWriter::Csv(writer) => writer.write_byte_record(header)?,
+            RecordWriter::Ndjson { keys, .. } | RecordWriter::JsonArray { keys, .. } => {
+                *keys = header
+                    .iter()
+                    .map(|key| String::from_utf8_lossy(key).into_owned())
+                    .collect();
+            }
+            RecordWriter::Markdown { header: kept, .. } => {
+                *kept = Some(header.iter().map(markdown_cell).collect());
+            }
+            RecordWriter::Table { header: kept, .. } => {
+                *kept = Some(header.iter().map(table_cell).collect());
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a single cleansed record.
+    pub fn write_record(&mut self, record: &ByteRecord) -> Result<(), CleanseError> {
+        match self {
+            RecordWriter::Csv(writer) => writer.write_byte_record(record)?,
+            RecordWriter::Ndjson { writer, keys } => {
+                write_object(writer, keys, record)?;
+                writer.write_all(b"\n")?;
+            }
+            RecordWriter::JsonArray {
+                writer,
+                keys,
+                first_record,
+            } => {
+                writer.write_all(if *first_record { b"[\n" } else { b",\n" })?;
+                *first_record = false;
+                write_object(writer, keys, record)?;
+            }
+            RecordWriter::Markdown { rows, .. } => {
+                rows.push(record.iter().map(markdown_cell).collect())
+            }
+            RecordWriter::Table { rows, .. } => rows.push(record.iter().map(table_cell).collect()),
+        }
+        Ok(())
+    }
+
+    /// Write anything that has to follow the last record, such as the end of a JSON array, or for
+    /// the table formats, the whole table.
+    pub fn finish(&mut self) -> Result<(), CleanseError> {
+        match self {
+            RecordWriter::JsonArray {
+                writer,
+                first_record,
+                ..
+            } => {
+                writer.write_all(if *first_record { b"[]\n" } else { b"\n]\n" })?;
+            }
+            RecordWriter::Markdown {
+                writer,
+                header,
+                rows,
+            } => write_markdown_table(writer, header.take(), rows)?,
+            RecordWriter::Table {
+                writer,
+                header,
+                rows,
+            } => write_box_table(writer, header.take().as_ref(), rows)?,
+            RecordWriter::Csv(_) | RecordWriter::Ndjson { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Flush any buffered output, returning the output it was written to.
+    pub fn into_inner(self) -> Result<W, CleanseError> {
+        let mut writer = match self {
+            RecordWriter::Csv(writer) => writer
+                .into_inner()
+                .map_err(|err| io::Error::new(err.error().kind(), err.error().to_string()))?,
+            RecordWriter::Ndjson { writer, .. }
+            | RecordWriter::JsonArray { writer, .. }
+            | RecordWriter::Markdown { writer, .. }
+            | RecordWriter::Table { writer, .. } => writer,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Flush any buffered output.
+    pub fn flush(&mut self) -> Result<(), CleanseError> {
+        match self {
+            RecordWriter::Csv(writer) => writer.flush()?,
+            RecordWriter::Ndjson { writer, .. }
+            | RecordWriter::JsonArray { writer, .. }
+            | RecordWriter::Markdown { writer, .. }
+            | RecordWriter::Table { writer, .. } => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// An output shared by several runs, such as one for each of the FILEs, so that their records are
+/// written as one JSON array or table. Its [`RecordWriter`] is made for the first run that writes
+/// to it, after any delimiter detection.
+pub struct SharedOutput<W: Write> {
+    output: Option<W>,
+    writer: Option<RecordWriter<W>>,
+}
+
+impl<W: Write> SharedOutput<W> {
+    pub fn new(output: W) -> Self {
+        SharedOutput {
+            output: Some(output),
+            writer: None,
+        }
+    }
+
+    /// The writer for the output, made for `opts` if no run has written to it yet.
+    pub fn writer(&mut self, opts: &CleanseOptions) -> &mut RecordWriter<W> {
+        let output = &mut self.output;
+        self.writer.get_or_insert_with(|| {
+            RecordWriter::new(output.take().expect("The writer is only made once"), opts)
+        })
+    }
+
+    /// Write anything that has to follow the last record of the last run, unless this is a dry
+    /// run, returning the output it was written to.
+    pub fn finish(self, opts: &CleanseOptions) -> Result<W, CleanseError> {
+        let mut writer = match (self.writer, self.output) {
+            (Some(writer), _) => writer,
+            (None, Some(output)) => RecordWriter::new(output, opts),
+            (None, None) => unreachable!("The output is only taken by its writer"),
+        };
+        if !opts.dry_run {
+            writer.finish()?;
+        }
+        writer.into_inner()
+    }
+}
+
+/// Write `record` as a JSON object, keyed by `keys` or by field index past the end of `keys`.
+///
+/// This is written by hand rather than through a map to keep the fields in order.
+fn write_object<W: Write>(
+    writer: &mut W,
+    keys: &[String],
+    record: &ByteRecord,
+) -> Result<(), CleanseError> {
+    writer.write_all(b"{")?;
+    for (i, field) in record.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        match keys.get(i) {
+            Some(key) => serde_json::to_writer(&mut *writer, key)?,
+            None => serde_json::to_writer(&mut *writer, &i.to_string())?,
+        }
+        writer.write_all(b":")?;
+        serde_json::to_writer(&mut *writer, &String::from_utf8_lossy(field))?;
+    }
+    writer.write_all(b"}")?;
+    Ok(())
+}
+
+/// A field as the text of a Markdown table cell, with any pipes escaped.
+fn markdown_cell(field: &[u8]) -> String {
+    String::from_utf8_lossy(field).replace('|', "\\|")
+}
+
+/// Write `rows` as a Markdown table, padding each cell with spaces to the width in bytes of the
+/// widest cell in its column. Without a header the columns are headed by their index.
+fn write_markdown_table<W: Write>(
+    writer: &mut W,
+    header: Option<Vec<String>>,
+    rows: &[Vec<String>],
+) -> Result<(), CleanseError> {
+    let columns = rows
+        .iter()
+        .chain(&header)
+        .map(|row| row.len())
+        .max()
+        .unwrap_or(0);
+    if columns == 0 {
+        return Ok(());
+    }
+    let header = header.unwrap_or_else(|| (0..columns).map(|i| i.to_string()).collect());
+    // The separator row needs at least three dashes
+    let mut widths = vec![3; columns];
+    for row in rows.iter().chain(iter::once(&header)) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let separator: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    for row in iter::once(&header)
+        .chain(iter::once(&separator))
+        .chain(rows)
+    {
+        writer.write_all(b"|")?;
+        for (i, width) in widths.iter().enumerate() {
+            let cell = row.get(i).map_or("", String::as_str);
+            write!(writer, " {}{} |", cell, " ".repeat(width - cell.len()))?;
+        }
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// A field as the text of a box drawn table cell, with any line breaks and tabs replaced with a
+/// space so that the cell stays on one line.
+fn table_cell(field: &[u8]) -> String {
+    String::from_utf8_lossy(field).replace(['\r', '\n', '\t'], " ")
+}
+
+/// Write `rows` as a table drawn with box drawing characters, padding each cell with spaces to
+/// the width in characters of the widest cell in its column. The header, if any, is separated
+/// from the rest of the rows by a line.
+fn write_box_table<W: Write>(
+    writer: &mut W,
+    header: Option<&Vec<String>>,
+    rows: &[Vec<String>],
+) -> Result<(), CleanseError> {
+    let mut widths: Vec<usize> = vec![];
+    for row in header.into_iter().chain(rows) {
+        if widths.len() < row.len() {
+            widths.resize(row.len(), 0);
+        }
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    if widths.is_empty() {
+        return Ok(());
+    }
+
+    let line = |left: &str, middle: &str, right: &str| {
+        let segments: Vec<String> = widths.iter().map(|&width| "─".repeat(width + 2)).collect();
+        format!("{}{}{}\n", left, segments.join(middle), right)
+    };
+    let write_row = |writer: &mut W, row: &[String]| -> Result<(), CleanseError> {
+        writer.write_all("│".as_bytes())?;
+        for (i, width) in widths.iter().enumerate() {
+            let cell = row.get(i).map_or("", String::as_str);
+            let padding = width - cell.chars().count();
+            write!(writer, " {}{} │", cell, " ".repeat(padding))?;
+        }
+        writer.write_all(b"\n")?;
+        Ok(())
+    };
+
+    writer.write_all(line("┌", "┬", "┐").as_bytes())?;
+    if let Some(header) = header {
+        write_row(writer, header)?;
+        writer.write_all(line("├", "┼", "┤").as_bytes())?;
+    }
+    for row in rows {
+        write_row(writer, row)?;
+    }
+    writer.write_all(line("└", "┴", "┘").as_bytes())?;
+    Ok(())
+}
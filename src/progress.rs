@@ -0,0 +1,95 @@
+//! A minimal progress indicator drawn to stderr.
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shows the number of records processed and the read rate on stderr.
+///
+/// When the total number of input bytes is known a percentage is shown as well, otherwise the
+/// display is an indefinite spinner.
+#[derive(Debug)]
+pub struct Progress {
+    total_bytes: Option<u64>,
+    start: Instant,
+    last_draw: Option<Instant>,
+    ticks: usize,
+}
+
+impl Progress {
+    /// Create a new progress indicator, `total_bytes` is the size of the input if known.
+    pub fn new(total_bytes: Option<u64>) -> Self {
+        Self {
+            total_bytes,
+            start: Instant::now(),
+            last_draw: None,
+            ticks: 0,
+        }
+    }
+
+    /// Update the display, redrawing at most once every 100ms.
+    pub fn update(&mut self, records: u64, bytes: u64) {
+        let now = Instant::now();
+        if let Some(last_draw) = self.last_draw {
+            if now.duration_since(last_draw) < REDRAW_INTERVAL {
+                return;
+            }
+        }
+        self.last_draw = Some(now);
+        self.ticks += 1;
+        self.draw(records, bytes, "");
+    }
+
+    /// Draw the final state of the display and move to a new line.
+    pub fn finish(&mut self, records: u64, bytes: u64) {
+        self.draw(records, bytes, "\n");
+    }
+
+    fn draw(&self, records: u64, bytes: u64, end: &str) {
+        let line = self.format(records, bytes, self.start.elapsed());
+        // Errors writing progress are not worth failing the run over
+        let _ = write!(io::stderr(), "\r{}{}", line, end);
+    }
+
+    fn format(&self, records: u64, bytes: u64, elapsed: Duration) -> String {
+        let mb = bytes as f64 / 1_000_000.0;
+        let rate = mb / elapsed.as_secs_f64().max(f64::EPSILON);
+        let spinner = SPINNER[self.ticks % SPINNER.len()];
+        match self.total_bytes {
+            Some(total) if total > 0 => format!(
+                "{} {} records, {:.1}/{:.1} MB ({:.1}%), {:.1} MB/s",
+                spinner,
+                records,
+                mb,
+                total as f64 / 1_000_000.0,
+                100.0 * bytes as f64 / total as f64,
+                rate
+            ),
+            _ => format!(
+                "{} {} records, {:.1} MB, {:.1} MB/s",
+                spinner, records, mb, rate
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let progress = Progress::new(Some(4_000_000));
+        assert_eq!(
+            progress.format(10, 1_000_000, Duration::from_secs(2)),
+            "| 10 records, 1.0/4.0 MB (25.0%), 0.5 MB/s"
+        );
+
+        let progress = Progress::new(None);
+        assert_eq!(
+            progress.format(10, 1_000_000, Duration::from_secs(2)),
+            "| 10 records, 1.0 MB, 0.5 MB/s"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! `serde` support for the changes made to fields, so they can be written as JSON.
+//!
+//! [`CleanseChanges`] is written as a snake case string such as `"null_byte_replacement"`, and
+//! [`FieldChange`] as a map of its fields.
+use crate::{CleanseChanges, FieldChange};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+impl CleanseChanges {
+    /// Every kind of change, in the order they are made.
+    pub const ALL: [CleanseChanges; 16] = [
+        CleanseChanges::NullByteReplacement,
+        CleanseChanges::DelimiterReplacement,
+        CleanseChanges::TerminatorReplacement,
+        CleanseChanges::CarriageReturnReplacement,
+        CleanseChanges::FixedEncoding,
+        CleanseChanges::RegexReplacement,
+        CleanseChanges::WhitespaceTrimmed,
+        CleanseChanges::FieldTruncated,
+        CleanseChanges::ControlCharReplacement,
+        CleanseChanges::EmptyFieldReplaced,
+        CleanseChanges::NumericNormalized,
+        CleanseChanges::WhitespaceCollapsed,
+        CleanseChanges::Base64Encoded,
+        CleanseChanges::HexEncoded,
+        CleanseChanges::ValueMapped,
+        CleanseChanges::UnicodeNormalized,
+    ];
+
+    /// The snake case name of the variant, as it is serialized.
+    pub fn name(&self) -> &'static str {
+        // `ALL` is in declaration order, which `test_all_in_order` checks
+        VARIANT_NAMES[*self as usize]
+    }
+}
+
+/// The name of each variant of `CleanseChanges::ALL`, in the same order.
+const VARIANT_NAMES: &[&str; CleanseChanges::ALL.len()] = &[
+    "null_byte_replacement",
+    "delimiter_replacement",
+    "terminator_replacement",
+    "carriage_return_replacement",
+    "fixed_encoding",
+    "regex_replacement",
+    "whitespace_trimmed",
+    "field_truncated",
+    "control_char_replacement",
+    "empty_field_replaced",
+    "numeric_normalized",
+    "whitespace_collapsed",
+    "base64_encoded",
+    "hex_encoded",
+    "value_mapped",
+    "unicode_normalized",
+];
+
+impl Serialize for CleanseChanges {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for CleanseChanges {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        CleanseChanges::ALL
+            .iter()
+            .copied()
+            .find(|change| change.name() == name)
+            .ok_or_else(|| de::Error::unknown_variant(&name, &VARIANT_NAMES[..]))
+    }
+}
+
+const FIELD_CHANGE_FIELDS: &[&str] = &["record", "field", "kind", "original", "cleaned"];
+
+impl Serialize for FieldChange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("FieldChange", FIELD_CHANGE_FIELDS.len())?;
+        state.serialize_field("record", &self.record)?;
+        state.serialize_field("field", &self.field)?;
+        state.serialize_field("kind", &self.kind)?;
+        state.serialize_field("original", &self.original)?;
+        state.serialize_field("cleaned", &self.cleaned)?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldChange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("FieldChange", FIELD_CHANGE_FIELDS, FieldChangeVisitor)
+    }
+}
+
+struct FieldChangeVisitor;
+
+impl<'de> Visitor<'de> for FieldChangeVisitor {
+    type Value = FieldChange;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a field change")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut record, mut field, mut kind, mut original, mut cleaned) =
+            (None, None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "record" => record = Some(map.next_value()?),
+                "field" => field = Some(map.next_value()?),
+                "kind" => kind = Some(map.next_value()?),
+                "original" => original = Some(map.next_value()?),
+                "cleaned" => cleaned = Some(map.next_value()?),
+                other => return Err(de::Error::unknown_field(other, FIELD_CHANGE_FIELDS)),
+            }
+        }
+        Ok(FieldChange {
+            record: record.ok_or_else(|| de::Error::missing_field("record"))?,
+            field: field.ok_or_else(|| de::Error::missing_field("field"))?,
+            kind: kind.ok_or_else(|| de::Error::missing_field("kind"))?,
+            original: original.ok_or_else(|| de::Error::missing_field("original"))?,
+            cleaned: cleaned.ok_or_else(|| de::Error::missing_field("cleaned"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_all_in_order() {
+        for (i, change) in CleanseChanges::ALL.iter().enumerate() {
+            assert_eq!(*change as usize, i, "{:?} is out of order in ALL", change);
+        }
+    }
+
+    #[test]
+    fn test_changes_round_trip() {
+        for (change, name) in CleanseChanges::ALL.iter().zip(VARIANT_NAMES) {
+            let json = serde_json::to_string(change).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            assert_eq!(
+                serde_json::from_str::<CleanseChanges>(&json).unwrap(),
+                *change
+            );
+        }
+        assert!(serde_json::from_str::<CleanseChanges>("\"NullByteReplacement\"").is_err());
+    }
+
+    #[test]
+    fn test_field_change_round_trip() {
+        let change = FieldChange {
+            record: 3,
+            field: 1,
+            kind: CleanseChanges::FixedEncoding,
+            original: b"caf\xe9".to_vec(),
+            cleaned: String::from("caf\u{FFFD}"),
+        };
+        let json = serde_json::to_string(&change).unwrap();
+        assert_eq!(
+            json,
+            "{\"record\":3,\"field\":1,\"kind\":\"fixed_encoding\",\"original\":[99,97,102,233],\
+             \"cleaned\":\"caf\u{FFFD}\"}"
+        );
+        assert_eq!(serde_json::from_str::<FieldChange>(&json).unwrap(), change);
+        assert!(serde_json::from_str::<FieldChange>("{\"record\":3}").is_err());
+    }
+}
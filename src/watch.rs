@@ -0,0 +1,77 @@
+//! Following input that is still being written to, like `tail -f`.
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+/// Wraps a reader so that reaching the end of it waits for more input instead of ending it.
+///
+/// With no poll interval the reader is passed through as is. With one, each read that finds no
+/// more input sleeps for the interval and then tries again, so reads only ever return once there
+/// is more input or on an error, and the input never ends.
+#[derive(Debug)]
+pub struct Follow<R> {
+    inner: R,
+    interval: Option<Duration>,
+}
+
+impl<R: Read> Follow<R> {
+    /// Follow `inner`, polling it for more input every `interval` if set.
+    pub fn new(inner: R, interval: Option<Duration>) -> Self {
+        Self { inner, interval }
+    }
+}
+
+impl<R: Read> Read for Follow<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.inner.read(buf)?;
+            match self.interval {
+                Some(interval) if len == 0 && !buf.is_empty() => thread::sleep(interval),
+                _ => return Ok(len),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns each chunk in turn, with the end of the input in between them.
+    struct Growing {
+        chunks: Vec<&'static [u8]>,
+        at_end: bool,
+    }
+
+    impl Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.at_end = !self.at_end;
+            if self.at_end || self.chunks.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_follow() {
+        let growing = Growing {
+            chunks: vec![b"a,b\n", b"c,d\n"],
+            at_end: false,
+        };
+        let mut follow = Follow::new(growing, Some(Duration::from_millis(1)));
+        let mut buf = [0; 8];
+        assert_eq!(follow.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"a,b\n");
+        assert_eq!(follow.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"c,d\n");
+
+        let at_end = Growing {
+            chunks: vec![],
+            at_end: false,
+        };
+        assert_eq!(Follow::new(at_end, None).read(&mut buf).unwrap(), 0);
+    }
+}
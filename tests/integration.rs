@@ -0,0 +1,98 @@
+//! End to end tests running files through `run` twice, checking that cleansing is stable.
+use cleanse::{run, CleanseOptions, RegexReplace};
+use csv::QuoteStyle;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// A messy CSV with something for each of the fixes to do.
+const DIRTY: &[u8] = b"\
+id,name,notes\r\n\
+1,\"Smith, John\",\"line one\nline two\"\r\n\
+2,  padded  ,\"carriage\rreturn\"\n\
+3,\"nul\0byte\",\"tab\there\"\n\
+4,caf\xe9,\"\x01control\x7f\"\n\
+5,\"  lots   of\t\tspace \",\"\"\"quoted\"\"\"\n\
+6,,\"a\r\n\r\nb\"\n";
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cleanse_{}_{}", name, std::process::id()))
+}
+
+fn cleanse_file(input: &Path, output: &Path, opts: &CleanseOptions) {
+    run(
+        File::open(input).unwrap(),
+        File::create(output).unwrap(),
+        opts,
+    )
+    .unwrap();
+}
+
+/// Cleanse `DIRTY` with `opts`, then cleanse the result again, checking that the second pass
+/// changes nothing.
+fn assert_stable(name: &str, opts: &CleanseOptions) {
+    let dirty = temp_path(&format!("{}_dirty.csv", name));
+    let once = temp_path(&format!("{}_once.csv", name));
+    let twice = temp_path(&format!("{}_twice.csv", name));
+    fs::write(&dirty, DIRTY).unwrap();
+
+    cleanse_file(&dirty, &once, opts);
+    cleanse_file(&once, &twice, opts);
+    let (once_bytes, twice_bytes) = (fs::read(&once).unwrap(), fs::read(&twice).unwrap());
+    for path in [&dirty, &once, &twice] {
+        fs::remove_file(path).unwrap();
+    }
+
+    assert_ne!(once_bytes, DIRTY, "{}: nothing was cleansed", name);
+    assert_eq!(
+        String::from_utf8_lossy(&once_bytes),
+        String::from_utf8_lossy(&twice_bytes),
+        "{}: the second pass changed the output",
+        name
+    );
+}
+
+fn csv_opts() -> CleanseOptions {
+    CleanseOptions::default()
+        .delimiter(b',')
+        .output_delimiter(b',')
+}
+
+#[test]
+fn test_default_is_stable() {
+    assert_stable("default", &csv_opts());
+}
+
+#[test]
+fn test_headers_are_stable() {
+    assert_stable("headers", &csv_opts().has_headers(true));
+}
+
+#[test]
+fn test_all_fixes_are_stable() {
+    let opts = csv_opts()
+        .trim(true)
+        .collapse_whitespace(true)
+        .strip_control(true)
+        .empty_replacement(Some(String::from("NULL")))
+        .max_field_length(Some(8))
+        .regex_replacements(vec!["(?i)smith=SMITH".parse::<RegexReplace>().unwrap()]);
+    assert_stable("all_fixes", &opts);
+}
+
+#[test]
+fn test_quote_styles_are_stable() {
+    for (name, quote_style) in [
+        ("always", QuoteStyle::Always),
+        ("non_numeric", QuoteStyle::NonNumeric),
+    ] {
+        assert_stable(name, &csv_opts().quote_style(quote_style));
+    }
+}
+
+#[test]
+fn test_multi_byte_replacement_is_stable() {
+    let opts = csv_opts()
+        .replacement(String::from("«·»"))
+        .terminator_replacement(Some(String::from("\\n")));
+    assert_stable("multi_byte", &opts);
+}
@@ -0,0 +1,95 @@
+//! Property tests for `cleanse_field`, over generated inputs.
+//!
+//! Inputs come from a small seeded xorshift generator, so every run checks the same cases and
+//! any failure can be reproduced from the printed seed.
+use cleanse::{cleanse_field, CleanseOptions};
+
+/// Number of generated inputs to check per property.
+const CASES: u64 = 20_000;
+
+/// A xorshift64* generator, plenty for generating test inputs.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// A byte string biased towards the bytes that `cleanse_field` treats specially.
+    fn field(&mut self, delimiter: u8) -> Vec<u8> {
+        const SPECIAL: &[u8] = b"\0\r\n\t ,;|\"\x01\x7f\x80\xbf\xc3\xe2\xff";
+        let len = self.below(40) as usize;
+        (0..len)
+            .map(|_| match self.below(4) {
+                0 => SPECIAL[self.below(SPECIAL.len() as u64) as usize],
+                1 => delimiter,
+                _ => self.below(256) as u8,
+            })
+            .collect()
+    }
+}
+
+/// Generate option sets covering the optional steps, with an ASCII delimiter.
+fn options(rng: &mut Rng) -> CleanseOptions {
+    CleanseOptions::default()
+        .delimiter(rng.below(0x80) as u8)
+        .trim(rng.below(2) == 0)
+        .collapse_whitespace(rng.below(2) == 0)
+        .strip_control(rng.below(2) == 0)
+        .max_field_length(if rng.below(4) == 0 {
+            Some(rng.below(20) as usize)
+        } else {
+            None
+        })
+}
+
+#[test]
+fn cleanse_field_is_idempotent() {
+    let mut rng = Rng::new(0x0C1E_A05E);
+    for case in 0..CASES {
+        let opts = options(&mut rng);
+        let bytes = rng.field(opts.delimiter);
+        let (once, _) = cleanse_field(&bytes, &opts, 0, 0);
+        let (twice, changes) = cleanse_field(once.as_bytes(), &opts, 0, 0);
+        assert_eq!(
+            once, twice,
+            "case {}: input {:?} with delimiter {:?}",
+            case, bytes, opts.delimiter as char
+        );
+        assert!(
+            changes.is_empty(),
+            "case {}: second pass over {:?} still changed {:?}",
+            case,
+            once,
+            changes
+        );
+    }
+}
+
+#[test]
+fn cleanse_field_never_panics() {
+    let mut rng = Rng::new(0xDEAD_BEEF);
+    for _ in 0..CASES {
+        // Any delimiter byte at all, including ones that can't start a UTF-8 character
+        let delimiter = rng.below(256) as u8;
+        let opts = options(&mut rng).delimiter(delimiter);
+        let bytes = rng.field(opts.delimiter);
+        let (cleaned, _) = cleanse_field(&bytes, &opts, 0, 0);
+        // The replacement itself is the only way to end up with an ASCII delimiter
+        if opts.delimiter < 0x80 && opts.delimiter != b' ' {
+            assert!(!cleaned.contains(opts.delimiter as char), "{:?}", bytes);
+        }
+    }
+}